@@ -0,0 +1,66 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read};
+use crate::utilities::build_invalid_data_error_string;
+
+/// Client uses this variable when no token file or variable is set on the command line.
+pub const DEFAULT_TOKEN_ENV: &str = "RUNNER_TOKEN";
+
+/// Reads the token from the file or from the environment variable, the file has priority.
+pub fn load_token(token_file: &Option<String>, token_env: &Option<String>) -> Result<Option<String>, Error> {
+    let token = if let Some(file) = token_file {
+        fs::read_to_string(file)
+            .map_err(|e| build_invalid_data_error_string(format!("could not read token file {}: {}", file, e)))?
+    } else if let Some(name) = token_env {
+        env::var(name)
+            .map_err(|_e| build_invalid_data_error_string(format!("token variable {} is not set", name)))?
+    } else {
+        return Ok(None);
+    };
+    let token = token.trim().to_string();
+    if token.is_empty() || token.contains(char::is_whitespace) {
+        return Err(build_invalid_data_error_string("token must be non empty and must not contain spaces".to_string()));
+    }
+    Ok(Some(token))
+}
+
+pub fn generate_token() -> Result<String, Error> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Compares all the bytes so the time does not depend on the matching prefix length.
+fn tokens_equal(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len() &&
+        expected.bytes().zip(provided.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+pub fn check_token(expected: &Option<String>, provided: &Option<String>) -> Result<(), Error> {
+    match (expected, provided) {
+        (None, _) => Ok(()),
+        (Some(e), Some(p)) if tokens_equal(e, p) => Ok(()),
+        (Some(_), Some(_)) => Err(Error::new(ErrorKind::PermissionDenied, "invalid token")),
+        (Some(_), None) => Err(Error::new(ErrorKind::PermissionDenied, "token required")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{check_token, generate_token};
+
+    #[test]
+    fn test_check_token() {
+        let token = Some("secret".to_string());
+        let expected = Some("secret".to_string());
+        assert!(check_token(&expected, &token).is_ok());
+        assert!(check_token(&expected, &Some("secreT".to_string())).is_err());
+        assert!(check_token(&expected, &Some("secret1".to_string())).is_err());
+        assert!(check_token(&expected, &None).is_err());
+        assert!(check_token(&None, &None).is_ok());
+        let generated = generate_token().unwrap();
+        assert_eq!(generated.len(), 32);
+        assert_ne!(generated, generate_token().unwrap());
+    }
+}
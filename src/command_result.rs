@@ -0,0 +1,96 @@
+use std::io::{Error, ErrorKind};
+use serde_json::{json, Map, Value};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Result<OutputFormat, Error> {
+        match name {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("unknown output format {}", name)))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+        }
+    }
+}
+
+pub const EXIT_FAILED: i32 = 1;
+pub const EXIT_INVALID: i32 = 2;
+pub const EXIT_NOT_FOUND: i32 = 3;
+pub const EXIT_TIMED_OUT: i32 = 4;
+
+pub fn error_code(error: &Error) -> &'static str {
+    match error.kind() {
+        ErrorKind::NotFound => "not_found",
+        ErrorKind::InvalidInput => "invalid_input",
+        ErrorKind::InvalidData => "invalid_data",
+        ErrorKind::TimedOut => "timed_out",
+        ErrorKind::PermissionDenied => "unauthorized",
+        ErrorKind::Unsupported => "unsupported",
+        _ => "failed"
+    }
+}
+
+pub fn exit_code(code: &str) -> i32 {
+    match code {
+        "not_found" => EXIT_NOT_FOUND,
+        "invalid_input" | "invalid_data" | "unsupported" => EXIT_INVALID,
+        "timed_out" => EXIT_TIMED_OUT,
+        _ => EXIT_FAILED
+    }
+}
+
+pub fn build_result(command: &[String], outcomes: Vec<Value>, messages: Vec<String>,
+                    result: &Result<Option<Value>, Error>) -> Value {
+    let mut object = Map::new();
+    object.insert("command".to_string(), json!(command));
+    object.insert("success".to_string(), json!(result.is_ok()));
+    object.insert("outcomes".to_string(), Value::Array(outcomes));
+    object.insert("messages".to_string(), json!(messages));
+    match result {
+        Ok(Some(data)) => { object.insert("data".to_string(), data.clone()); }
+        Ok(None) => {}
+        Err(e) => {
+            object.insert("error".to_string(), json!({"code": error_code(e), "message": e.to_string()}));
+        }
+    }
+    Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Error, ErrorKind};
+    use serde_json::json;
+    use crate::command_result::{build_result, error_code, exit_code, EXIT_FAILED, EXIT_INVALID, EXIT_NOT_FOUND,
+                                EXIT_TIMED_OUT};
+
+    #[test]
+    fn test_build_result() {
+        let command = vec!["stop".to_string(), "a".to_string()];
+        let result = build_result(&command, vec![], vec![],
+                                  &Err(Error::new(ErrorKind::TimedOut, "timed out")));
+        assert_eq!(result, json!({"command": ["stop", "a"], "success": false, "outcomes": [], "messages": [],
+            "error": {"code": "timed_out", "message": "timed out"}}));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let code = |kind| exit_code(error_code(&Error::new(kind, "error")));
+        assert_eq!(code(ErrorKind::NotFound), EXIT_NOT_FOUND);
+        assert_eq!(code(ErrorKind::InvalidInput), EXIT_INVALID);
+        assert_eq!(code(ErrorKind::InvalidData), EXIT_INVALID);
+        assert_eq!(code(ErrorKind::TimedOut), EXIT_TIMED_OUT);
+        assert_eq!(code(ErrorKind::Other), EXIT_FAILED);
+        assert_eq!(code(ErrorKind::PermissionDenied), EXIT_FAILED);
+    }
+}
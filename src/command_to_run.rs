@@ -1,11 +1,17 @@
 use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::{env, io};
 use std::io::{Error, Write};
+use std::os::unix::process::CommandExt;
 use std::process::{Child, Command, Stdio};
-use env_file::parse_env_file;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 use split_string::split_string;
+use crate::environment::Environment;
+use crate::signals::send_group_signal;
 use crate::utilities::build_invalid_data_error_str;
 
 pub struct CommandToRun {
@@ -14,7 +20,9 @@ pub struct CommandToRun {
     log_file_out: Option<String>,
     log_file_err: Option<String>,
     work_dir: Option<String>,
-    env_file: Option<String>,
+    env: Environment,
+    // stdout log length at the last spawn, log patterns are matched against the current run output only
+    log_offset: Arc<AtomicU64>,
 }
 
 fn format_vector(vector: &Vec<String>) -> String {
@@ -24,21 +32,25 @@ fn format_vector(vector: &Vec<String>) -> String {
     "[\"".to_string() + vector.join("\",\"").as_str() + "\"]"
 }
 
+fn open_log_file(file_name: &str, truncate: bool) -> Result<File, Error> {
+    OpenOptions::new().create(true).write(true).truncate(truncate).append(!truncate).open(file_name)
+}
+
 impl Display for CommandToRun {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "CommandToRun {} parameters={} log_file_out={} log_file_err={} work_dir={} env_file={}",
+        write!(f, "CommandToRun {} parameters={} log_file_out={} log_file_err={} work_dir={} env_files={}",
                self.command,
                format_vector(&self.parameters),
                if self.log_file_out.is_none() { "None" } else { self.log_file_out.as_ref().unwrap().as_str() },
                if self.log_file_err.is_none() { "None" } else { self.log_file_err.as_ref().unwrap().as_str() },
                if self.work_dir.is_none() { "None" } else { self.work_dir.as_ref().unwrap().as_str() },
-               if self.env_file.is_none() { "None" } else { self.env_file.as_ref().unwrap().as_str() })
+               format_vector(&self.env.get_env_files().into_iter().cloned().collect()))
     }
 }
 
 impl CommandToRun {
     pub fn new(command: String, logfile_out: Option<String>, logfile_err: Option<String>, workdir: Option<String>,
-               env_file: Option<String>) -> Result<CommandToRun, Error> {
+               env: Environment) -> Result<CommandToRun, Error> {
         if command.is_empty() {
             return Err(build_invalid_data_error_str("command is empty"));
         }
@@ -69,25 +81,25 @@ impl CommandToRun {
             log_file_out,
             log_file_err,
             work_dir,
-            env_file,
+            env,
+            log_offset: Arc::new(AtomicU64::new(0)),
         })
     }
 
-    fn prepare(&self) -> Result<Command, Error> {
+    fn prepare(&self, append_logs: bool) -> Result<Command, Error> {
         let mut command = Command::new(&self.command);
         command.args(&self.parameters);
-        if let Some(f) = &self.env_file {
-            let env_variables = parse_env_file(CommandToRun::build_file_path(&f, &self.work_dir)?)?;
-            command.envs(&env_variables);
-        }
+        self.env.apply(&mut command, &self.work_dir)?;
         if let Some(work_dir) = &self.work_dir {
             command.current_dir(work_dir);
         }
         if let Some(log_file) = &self.log_file_out {
-            command.stdout(Stdio::from(File::create(log_file)?));
+            let file = open_log_file(log_file, !append_logs)?;
+            self.log_offset.store(file.metadata()?.len(), Ordering::Relaxed);
+            command.stdout(Stdio::from(file));
         }
         if let Some(log_file) = &self.log_file_err {
-            command.stderr(Stdio::from(File::create(log_file)?));
+            command.stderr(Stdio::from(open_log_file(log_file, !append_logs)?));
         }
         Ok(command)
     }
@@ -97,28 +109,99 @@ impl CommandToRun {
             println!("{}", self);
             return Ok(());
         }
-        let mut command = self.prepare()?;
+        let mut command = self.prepare(false)?;
         let output = command.output()?;
         io::stdout().write_all(&output.stdout)?;
         io::stderr().write_all(&output.stderr)
     }
 
-    pub fn run_async(&self, noexec: bool) -> Result<Option<Child>, Error> {
+    /// Restarts by the restart policy append to the log files to keep the crash output.
+    pub fn run_async(&self, noexec: bool, append_logs: bool) -> Result<Option<Child>, Error> {
         if noexec {
             println!("{}", self);
             return Ok(None);
         }
-        let mut command = self.prepare()?;
+        let mut command = self.prepare(append_logs)?;
+        // new process group allows to stop the whole process tree started by the command
+        command.process_group(0);
         command.spawn().map(|r| Some(r))
     }
 
+    /// Runs the command with output discarded, the process group is killed when it is still
+    /// running after the timeout. Returns the command exit success flag.
+    pub fn run_with_timeout(&self, timeout: Duration) -> Result<bool, Error> {
+        let mut command = self.prepare(false)?;
+        command.stdout(Stdio::null());
+        command.stderr(Stdio::null());
+        command.process_group(0);
+        let mut child = command.spawn()?;
+        let started = Instant::now();
+        while started.elapsed() < timeout {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status.success());
+            }
+            sleep(Duration::from_millis(50));
+        }
+        let _ = send_group_signal(child.id(), libc::SIGKILL);
+        let _ = child.wait();
+        Ok(false)
+    }
+
+    pub fn get_env(&self) -> &Environment {
+        &self.env
+    }
+
+    pub fn get_work_dir(&self) -> &Option<String> {
+        &self.work_dir
+    }
+
+    pub fn get_log_file_out(&self) -> &Option<String> {
+        &self.log_file_out
+    }
+
+    pub fn get_log_file_err(&self) -> &Option<String> {
+        &self.log_file_err
+    }
+
+    pub fn get_log_offset(&self) -> Arc<AtomicU64> {
+        self.log_offset.clone()
+    }
+
     pub fn build_file_path(path: &String, work_dir: &Option<String>) -> Result<String, Error> {
         let cwd = env::current_dir()?;
         let mut result = path.replace("$PWD", &cwd.display().to_string());
-        result = result.replace("~", &env::var("HOME").unwrap());
+        // only the leading ~ is the home folder, ~ inside the arguments is kept
+        if result == "~" || result.starts_with("~/") {
+            result = result.replacen('~', &env::var("HOME").unwrap(), 1);
+        }
         if let Some(wd) = work_dir {
             result = result.replace("$WD", wd);
         }
         Ok(result)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::atomic::Ordering;
+    use crate::command_to_run::CommandToRun;
+    use crate::environment::Environment;
+
+    #[test]
+    fn test_log_file_append_on_restart() {
+        let file_name = std::env::temp_dir().join(format!("runner_command_test_{}.log", std::process::id()))
+            .to_string_lossy().to_string();
+        fs::write(&file_name, "previous run\n").unwrap();
+        let command = CommandToRun::new("/bin/echo line".to_string(), Some(file_name.clone()), None, None,
+                                        Environment::default()).unwrap();
+        command.run_async(false, false).unwrap().unwrap().wait().unwrap();
+        assert_eq!(command.get_log_offset().load(Ordering::Relaxed), 0);
+        command.run_async(false, true).unwrap().unwrap().wait().unwrap();
+        assert_eq!(fs::read_to_string(&file_name).unwrap(), "line\nline\n");
+        assert_eq!(command.get_log_offset().load(Ordering::Relaxed), 5);
+        command.run_async(false, false).unwrap().unwrap().wait().unwrap();
+        assert_eq!(fs::read_to_string(&file_name).unwrap(), "line\n");
+        fs::remove_file(&file_name).unwrap();
+    }
+}
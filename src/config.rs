@@ -0,0 +1,167 @@
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use yaml_rust::{Yaml, YamlLoader};
+use yaml_rust::yaml::Hash;
+use crate::command_result::OutputFormat;
+use crate::service_manager::ServiceManager;
+use crate::user_command::{run_user_commands, WriterWithTCP};
+use crate::utilities::{build_invalid_data_error_str, build_invalid_data_error_string};
+use crate::variables::interpolate_config;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+pub fn load_config(file_name: &str) -> Result<Yaml, Error> {
+    load_config_files(file_name).map(|(config, _files)| config)
+}
+
+/// Reads the configuration file with its includes, merges the override file on top
+/// and substitutes the variables, returns the configuration and the names of the files it was read from.
+pub fn load_config_files(file_name: &str) -> Result<(Yaml, Vec<String>), Error> {
+    let mut files = Vec::new();
+    let mut config = load_with_includes(file_name, &mut Vec::new(), &mut files)?;
+    let override_file = get_override_file_name(file_name);
+    if Path::new(&override_file).exists() {
+        let overrides = load_with_includes(&override_file, &mut Vec::new(), &mut files)?;
+        merge(&mut config, overrides);
+    }
+    if config.as_hash().is_none_or(|h| h.is_empty()) {
+        return Err(build_invalid_data_error_str("configuration file is empty"));
+    }
+    interpolate_config(&mut config, file_name)?;
+    Ok((config, files))
+}
+
+/// Returns example.override.yml for example.yml.
+fn get_override_file_name(file_name: &str) -> String {
+    format!("{}.override.yml", file_name.strip_suffix(".yml").unwrap_or(file_name))
+}
+
+fn load_yaml(file_name: &str) -> Result<Yaml, Error> {
+    let contents = fs::read_to_string(file_name)
+        .map_err(|e| Error::new(e.kind(), format!("{}: {}", file_name, e)))?;
+    let mut docs = YamlLoader::load_from_str(contents.as_str())
+        .map_err(|e| build_invalid_data_error_string(format!("{}: {}", file_name, e)))?;
+    if docs.is_empty() {
+        return Ok(Yaml::Hash(Hash::new()));
+    }
+    Ok(docs.swap_remove(0))
+}
+
+/// Included files are merged in the list order, the including file is merged on top of them.
+/// Include paths are relative to the including file folder.
+fn load_with_includes(file_name: &str, stack: &mut Vec<String>, files: &mut Vec<String>) -> Result<Yaml, Error> {
+    let mut config = load_yaml(file_name)?;
+    // the same file can be reached by different relative paths
+    let canonical_name = fs::canonicalize(file_name)?.to_string_lossy().to_string();
+    if stack.contains(&canonical_name) {
+        return Err(build_invalid_data_error_string(
+            format!("include cycle: {} -> {}", stack.join(" -> "), canonical_name)));
+    }
+    files.push(file_name.to_string());
+    let includes = match &mut config {
+        Yaml::Hash(hash) => hash.remove(&Yaml::String("include".to_string())),
+        _ => None
+    };
+    let includes = match includes {
+        None => return Ok(config),
+        Some(Yaml::Array(includes)) => includes,
+        Some(_) => return Err(build_invalid_data_error_string(format!("include is invalid in {}", file_name)))
+    };
+    stack.push(canonical_name);
+    let mut result = Yaml::Hash(Hash::new());
+    for include in includes {
+        let include = include.as_str()
+            .ok_or(build_invalid_data_error_string(format!("include is invalid in {}", file_name)))?;
+        let path = Path::new(file_name).parent()
+            .map(|folder| folder.join(include).to_string_lossy().to_string())
+            .unwrap_or(include.to_string());
+        merge(&mut result, load_with_includes(&path, stack, files)?);
+    }
+    stack.pop();
+    merge(&mut result, config);
+    Ok(result)
+}
+
+/// Mappings are merged key by key recursively, any other value (lists too) replaces the base value.
+fn merge(base: &mut Yaml, overriding: Yaml) {
+    match (base, overriding) {
+        (Yaml::Hash(base), Yaml::Hash(overriding)) => {
+            for (key, value) in overriding {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge(base_value, value),
+                    None => { base.insert(key, value); }
+                }
+            }
+        }
+        (base, overriding) => *base = overriding
+    }
+}
+
+fn get_modified_times(files: &[String]) -> Vec<Option<SystemTime>> {
+    files.iter()
+        .map(|file_name| fs::metadata(file_name).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Returns the configuration files with the override file, which may not exist yet.
+fn get_watched_files(file_name: &str) -> Option<Vec<String>> {
+    let (_config, mut files) = load_config_files(file_name).ok()?;
+    let override_file = get_override_file_name(file_name);
+    if !files.contains(&override_file) {
+        files.push(override_file);
+    }
+    Some(files)
+}
+
+/// Polls the configuration files modification times and runs reload command when any of them changes,
+/// the previous configuration is kept when the files can not be loaded.
+pub fn watch_config(file_name: String, manager: &'static ServiceManager, noexec: bool) {
+    thread::spawn(move || {
+        let mut files = get_watched_files(&file_name).unwrap_or(vec![file_name.clone()]);
+        let mut modified = get_modified_times(&files);
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let current = get_modified_times(&files);
+            if current != modified {
+                println!("Configuration file {} is changed, reloading...", file_name);
+                run_user_commands(vec!["reload".to_string()], manager, noexec,
+                                  WriterWithTCP::new(None, OutputFormat::Text, 0));
+                if let Some(new_files) = get_watched_files(&file_name) {
+                    files = new_files;
+                }
+                modified = get_modified_times(&files);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::config::load_config_files;
+
+    #[test]
+    fn test_load_config_files() {
+        let folder = std::env::temp_dir().join(format!("runner_config_test_{}", std::process::id()));
+        fs::create_dir_all(folder.join("common")).unwrap();
+        fs::write(folder.join("common/kafka.yml"), "services:\n  kafka:\n    scripts:\n      server:\n        command: kafka.sh\n        workdir: /opt/kafka\n").unwrap();
+        fs::write(folder.join("main.yml"), "include: [common/kafka.yml]\nservices:\n  kafka:\n    depends_on: [db]\n").unwrap();
+        fs::write(folder.join("main.override.yml"), "services:\n  kafka:\n    disabled: true\n    depends_on: []\n    scripts:\n      server:\n        workdir: /home/user/kafka\n").unwrap();
+        let file_name = folder.join("main.yml").to_string_lossy().to_string();
+        let (config, files) = load_config_files(&file_name).unwrap();
+        let kafka = &config["services"]["kafka"];
+        assert_eq!(kafka["scripts"]["server"]["command"].as_str(), Some("kafka.sh"));
+        assert_eq!(kafka["scripts"]["server"]["workdir"].as_str(), Some("/home/user/kafka"));
+        assert_eq!(kafka["disabled"].as_bool(), Some(true));
+        assert!(kafka["depends_on"].as_vec().unwrap().is_empty());
+        assert!(config["include"].is_badvalue());
+        assert_eq!(files.len(), 3);
+
+        fs::write(folder.join("common/kafka.yml"), "include: [../main.yml]\n").unwrap();
+        assert!(load_config_files(&file_name).unwrap_err().to_string().starts_with("include cycle: "));
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
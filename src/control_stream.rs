@@ -0,0 +1,258 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use yaml_rust::Yaml;
+use crate::utilities::build_invalid_data_error_string;
+
+const DEFAULT_SOCKET_MODE: u32 = 0o600;
+const DEFAULT_TCP_HOST: &str = "127.0.0.1";
+
+/// Control channel settings from the config control section and command line,
+/// unix socket is used unless tcp port is set.
+#[derive(Default)]
+pub struct ControlSettings {
+    pub socket: Option<String>,
+    pub socket_mode: Option<u32>,
+    pub port: Option<u16>,
+    pub host: Option<String>,
+    pub token_file: Option<String>,
+    pub token_env: Option<String>,
+    pub http_port: Option<u16>,
+    pub http_host: Option<String>,
+}
+
+pub enum ControlAddress {
+    Unix { path: String, mode: u32 },
+    Tcp { host: String, port: u16 },
+}
+
+pub enum ControlListener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+pub enum ControlStream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+/// Parses octal file mode like 600 or 0660.
+pub fn parse_socket_mode(mode: &str) -> Result<u32, Error> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(m) if m <= 0o777 => Ok(m),
+        _ => Err(build_invalid_data_error_string(format!("socket mode is invalid: {}", mode)))
+    }
+}
+
+fn parse_yaml_port(item: &Yaml, name: &str) -> Result<Option<u16>, Error> {
+    match item {
+        Yaml::BadValue => Ok(None),
+        Yaml::Integer(p) if *p > 0 && *p <= 65535 => Ok(Some(*p as u16)),
+        _ => Err(build_invalid_data_error_string(format!("{} is invalid", name)))
+    }
+}
+
+/// $XDG_RUNTIME_DIR/runner.sock or /tmp/runner-<uid>.sock when the runtime dir is not set.
+pub fn default_socket_path() -> String {
+    match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if !dir.is_empty() => format!("{}/runner.sock", dir),
+        _ => format!("/tmp/runner-{}.sock", unsafe { libc::getuid() })
+    }
+}
+
+impl ControlSettings {
+    pub fn from_yaml(item: &Yaml) -> Result<ControlSettings, Error> {
+        let socket_mode = match &item["socket_mode"] {
+            Yaml::BadValue => None,
+            Yaml::String(s) => Some(parse_socket_mode(s)?),
+            Yaml::Integer(i) => Some(parse_socket_mode(i.to_string().as_str())?),
+            _ => return Err(build_invalid_data_error_string("socket_mode is invalid".to_string()))
+        };
+        let port = parse_yaml_port(&item["tcp_port"], "tcp_port")?;
+        let http_port = parse_yaml_port(&item["http_port"], "http_port")?;
+        Ok(ControlSettings {
+            socket: item["socket"].as_str().map(|s| s.to_string()),
+            socket_mode,
+            port,
+            host: item["tcp_host"].as_str().map(|s| s.to_string()),
+            token_file: item["token_file"].as_str().map(|s| s.to_string()),
+            token_env: item["token_env"].as_str().map(|s| s.to_string()),
+            http_port,
+            http_host: item["http_host"].as_str().map(|s| s.to_string()),
+        })
+    }
+
+    /// Values set in other settings take precedence.
+    pub fn merge(self, other: ControlSettings) -> ControlSettings {
+        ControlSettings {
+            socket: other.socket.or(self.socket),
+            socket_mode: other.socket_mode.or(self.socket_mode),
+            port: other.port.or(self.port),
+            host: other.host.or(self.host),
+            token_file: other.token_file.or(self.token_file),
+            token_env: other.token_env.or(self.token_env),
+            http_port: other.http_port.or(self.http_port),
+            http_host: other.http_host.or(self.http_host),
+        }
+    }
+
+    pub fn get_http_address(&self) -> Option<String> {
+        self.http_port.map(|port| format!("{}:{}", self.http_host.as_deref().unwrap_or(DEFAULT_TCP_HOST), port))
+    }
+
+    pub fn get_address(&self) -> ControlAddress {
+        match self.port {
+            Some(port) => ControlAddress::Tcp {
+                host: self.host.clone().unwrap_or(DEFAULT_TCP_HOST.to_string()),
+                port,
+            },
+            None => ControlAddress::Unix {
+                path: self.socket.clone().unwrap_or_else(default_socket_path),
+                mode: self.socket_mode.unwrap_or(DEFAULT_SOCKET_MODE),
+            }
+        }
+    }
+}
+
+impl Display for ControlAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlAddress::Unix { path, .. } => write!(f, "unix socket {}", path),
+            ControlAddress::Tcp { host, port } => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl ControlAddress {
+    /// Stale socket file left by a killed server is removed, the socket is created
+    /// with the configured permissions.
+    pub fn bind(&self) -> Result<ControlListener, Error> {
+        match self {
+            ControlAddress::Unix { path, mode } => {
+                if Path::new(path).exists() {
+                    if UnixStream::connect(path).is_ok() {
+                        return Err(Error::new(ErrorKind::AddrInUse, format!("server is already running on {}", self)));
+                    }
+                    fs::remove_file(path)?;
+                }
+                // restrictive umask avoids a window when the socket is accessible by other users
+                let old_mask = unsafe { libc::umask(0o177) };
+                let listener = UnixListener::bind(path);
+                unsafe { libc::umask(old_mask) };
+                let listener = listener?;
+                fs::set_permissions(path, fs::Permissions::from_mode(*mode))?;
+                Ok(ControlListener::Unix(listener))
+            }
+            ControlAddress::Tcp { host, port } => {
+                Ok(ControlListener::Tcp(TcpListener::bind((host.as_str(), *port))?))
+            }
+        }
+    }
+
+    pub fn connect(&self) -> Result<ControlStream, Error> {
+        match self {
+            ControlAddress::Unix { path, .. } => Ok(ControlStream::Unix(UnixStream::connect(path)?)),
+            ControlAddress::Tcp { host, port } => {
+                let address = (host.as_str(), *port).to_socket_addrs()?.next()
+                    .ok_or(Error::new(ErrorKind::NotFound, format!("could not resolve {}", host)))?;
+                Ok(ControlStream::Tcp(TcpStream::connect(address)?))
+            }
+        }
+    }
+}
+
+impl ControlListener {
+    pub fn accept(&self) -> Result<ControlStream, Error> {
+        match self {
+            ControlListener::Unix(l) => l.accept().map(|(s, _a)| ControlStream::Unix(s)),
+            ControlListener::Tcp(l) => l.accept().map(|(s, _a)| ControlStream::Tcp(s)),
+        }
+    }
+}
+
+impl ControlStream {
+    pub fn get_peer(&self) -> String {
+        match self {
+            ControlStream::Unix(_s) => "unix socket".to_string(),
+            ControlStream::Tcp(s) => s.peer_addr().map(|a| a.to_string()).unwrap_or("unknown".to_string()),
+        }
+    }
+
+    pub fn try_clone(&self) -> Result<ControlStream, Error> {
+        match self {
+            ControlStream::Unix(s) => s.try_clone().map(ControlStream::Unix),
+            ControlStream::Tcp(s) => s.try_clone().map(ControlStream::Tcp),
+        }
+    }
+
+    pub fn shutdown(&self) -> Result<(), Error> {
+        match self {
+            ControlStream::Unix(s) => s.shutdown(Shutdown::Both),
+            ControlStream::Tcp(s) => s.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+impl Read for ControlStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ControlStream::Unix(s) => s.read(buf),
+            ControlStream::Tcp(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for ControlStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            ControlStream::Unix(s) => s.write(buf),
+            ControlStream::Tcp(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            ControlStream::Unix(s) => s.flush(),
+            ControlStream::Tcp(s) => s.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+    use crate::control_stream::{ControlAddress, ControlSettings, parse_socket_mode};
+
+    #[test]
+    fn test_parse_socket_mode() {
+        assert_eq!(parse_socket_mode("600").unwrap(), 0o600);
+        assert_eq!(parse_socket_mode("0660").unwrap(), 0o660);
+        assert!(parse_socket_mode("800").is_err());
+        assert!(parse_socket_mode("7777").is_err());
+    }
+
+    #[test]
+    fn test_settings() {
+        let docs = YamlLoader::load_from_str("socket: /tmp/a.sock\nsocket_mode: 660\ntcp_host: 0.0.0.0").unwrap();
+        let settings = ControlSettings::from_yaml(&docs[0]).unwrap();
+        assert!(settings.get_http_address().is_none());
+        match settings.get_address() {
+            ControlAddress::Unix { path, mode } => assert!(path == "/tmp/a.sock" && mode == 0o660),
+            _ => panic!("unix socket expected")
+        }
+        let settings = settings.merge(ControlSettings { port: Some(65000), ..Default::default() });
+        match settings.get_address() {
+            ControlAddress::Tcp { host, port } => assert!(host == "0.0.0.0" && port == 65000),
+            _ => panic!("tcp expected")
+        }
+        let docs = YamlLoader::load_from_str("http_port: 8080").unwrap();
+        let settings = ControlSettings::from_yaml(&docs[0]).unwrap();
+        assert_eq!(settings.get_http_address(), Some("127.0.0.1:8080".to_string()));
+        let docs = YamlLoader::load_from_str("http_port: 0").unwrap();
+        assert!(ControlSettings::from_yaml(&docs[0]).is_err());
+    }
+}
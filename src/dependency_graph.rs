@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::Error;
+use crate::utilities::build_invalid_data_error_string;
+
+pub struct DependencyGraph {
+    dependencies: BTreeMap<String, BTreeSet<String>>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VisitState {
+    InProgress,
+    Done,
+}
+
+impl DependencyGraph {
+    pub fn new() -> DependencyGraph {
+        DependencyGraph { dependencies: BTreeMap::new() }
+    }
+
+    pub fn add_node(&mut self, name: String) {
+        self.dependencies.entry(name).or_default();
+    }
+
+    pub fn add_dependency(&mut self, name: &str, dependency: String) {
+        self.dependencies.entry(name.to_string()).or_default().insert(dependency);
+    }
+
+    pub fn get_dependencies(&self, name: &String) -> HashSet<String> {
+        self.dependencies.get(name)
+            .map(|d| d.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn with_all_dependencies(&self, names: &HashSet<String>) -> HashSet<String> {
+        let mut result = HashSet::new();
+        let mut to_visit: Vec<&String> = names.iter().collect();
+        while let Some(name) = to_visit.pop() {
+            if result.insert(name.clone()) {
+                if let Some(dependencies) = self.dependencies.get(name) {
+                    to_visit.extend(dependencies.iter());
+                }
+            }
+        }
+        result
+    }
+
+    pub fn with_all_dependents(&self, names: &HashSet<String>) -> HashSet<String> {
+        let mut result = names.clone();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (name, dependencies) in &self.dependencies {
+                if !result.contains(name) && dependencies.iter().any(|d| result.contains(d)) {
+                    result.insert(name.clone());
+                    changed = true;
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the nodes ordered so that every node follows all its dependencies.
+    /// Fails when a dependency refers to an unknown node or when the graph has a cycle.
+    pub fn topological_order(&self) -> Result<Vec<String>, Error> {
+        let mut states = BTreeMap::new();
+        let mut result = Vec::new();
+        for name in self.dependencies.keys() {
+            let mut path = Vec::new();
+            self.visit(name, &mut states, &mut path, &mut result)?;
+        }
+        Ok(result)
+    }
+
+    fn visit<'a>(&'a self, name: &'a String, states: &mut BTreeMap<&'a String, VisitState>,
+                 path: &mut Vec<&'a String>, result: &mut Vec<String>) -> Result<(), Error> {
+        match states.get(name) {
+            Some(VisitState::Done) => return Ok(()),
+            Some(VisitState::InProgress) => {
+                let start = path.iter().position(|n| *n == name).unwrap_or(0);
+                let cycle = path[start..].iter()
+                    .map(|n| n.as_str())
+                    .chain([name.as_str()])
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                return Err(build_invalid_data_error_string(format!("dependency cycle: {}", cycle)));
+            }
+            None => {}
+        }
+        let dependencies = self.dependencies.get(name)
+            .ok_or(build_invalid_data_error_string(format!("unknown dependency {}", name)))?;
+        states.insert(name, VisitState::InProgress);
+        path.push(name);
+        for dependency in dependencies {
+            if !self.dependencies.contains_key(dependency) {
+                return Err(build_invalid_data_error_string(
+                    format!("{} depends on unknown script {}", name, dependency)));
+            }
+            self.visit(dependency, states, path, result)?;
+        }
+        path.pop();
+        states.insert(name, VisitState::Done);
+        result.push(name.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use crate::dependency_graph::DependencyGraph;
+
+    fn build_graph(edges: &[(&str, &str)]) -> DependencyGraph {
+        let mut graph = DependencyGraph::new();
+        for (name, dependency) in edges {
+            graph.add_node(name.to_string());
+            graph.add_node(dependency.to_string());
+            graph.add_dependency(name, dependency.to_string());
+        }
+        graph
+    }
+
+    #[test]
+    fn test_topological_order() {
+        let graph = build_graph(&[("server.server", "kafka.kafka"), ("kafka.kafka", "kafka.zookeeper"),
+            ("server.server", "postgres.postgres"), ("kafka.topic-create", "kafka.kafka")]);
+        let order = graph.topological_order().unwrap();
+        let position = |name: &str| order.iter().position(|n| n == name).unwrap();
+        assert_eq!(order.len(), 5);
+        assert!(position("kafka.zookeeper") < position("kafka.kafka"));
+        assert!(position("kafka.kafka") < position("server.server"));
+        assert!(position("kafka.kafka") < position("kafka.topic-create"));
+        assert!(position("postgres.postgres") < position("server.server"));
+
+        let all = graph.with_all_dependencies(&HashSet::from(["server.server".to_string()]));
+        assert_eq!(all.len(), 4);
+        assert!(!all.contains("kafka.topic-create"));
+
+        let dependents = graph.with_all_dependents(&HashSet::from(["kafka.zookeeper".to_string()]));
+        assert_eq!(dependents, HashSet::from(["kafka.zookeeper", "kafka.kafka", "kafka.topic-create", "server.server"]
+            .map(|n| n.to_string())));
+    }
+
+    #[test]
+    fn test_cycle() {
+        let graph = build_graph(&[("a.a", "b.b"), ("b.b", "c.c"), ("c.c", "a.a")]);
+        let error = graph.topological_order().unwrap_err().to_string();
+        assert_eq!(error, "dependency cycle: a.a -> b.b -> c.c -> a.a");
+    }
+
+    #[test]
+    fn test_unknown_node() {
+        let mut graph = DependencyGraph::new();
+        graph.add_node("a.a".to_string());
+        graph.add_dependency("a.a", "b.b".to_string());
+        assert!(graph.topological_order().is_err());
+    }
+}
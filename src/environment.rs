@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::io::Error;
+use std::process::Command;
+use env_file::parse_env_file;
+use yaml_rust::Yaml;
+use crate::command_to_run::CommandToRun;
+use crate::utilities::build_invalid_data_error_string;
+
+#[derive(Clone, Debug, PartialEq)]
+enum EnvLayer {
+    File(String),
+    Values(Vec<(String, String)>),
+}
+
+/// Command environment built from the config, service and script levels: every level adds
+/// its env files (env_file, then env_files list) and then its env map, later layers
+/// override the earlier ones. With clean_env the runner environment is not inherited,
+/// the nearest level setting it wins.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Environment {
+    clean: bool,
+    layers: Vec<EnvLayer>,
+}
+
+fn yaml_to_string(item: &Yaml) -> Option<String> {
+    match item {
+        Yaml::String(s) => Some(s.clone()),
+        Yaml::Integer(i) => Some(i.to_string()),
+        Yaml::Real(r) => Some(r.clone()),
+        Yaml::Boolean(b) => Some(b.to_string()),
+        _ => None
+    }
+}
+
+impl Environment {
+    pub fn with_level(&self, items: &Yaml, name: &str) -> Result<Environment, Error> {
+        let mut result = self.clone();
+        let build_error = |key: &str| build_invalid_data_error_string(format!("{} is invalid in {}", key, name));
+        match &items["clean_env"] {
+            Yaml::Boolean(clean) => result.clean = *clean,
+            Yaml::BadValue => {}
+            _ => return Err(build_error("clean_env"))
+        }
+        match &items["env_file"] {
+            Yaml::String(file) => result.layers.push(EnvLayer::File(file.clone())),
+            Yaml::BadValue => {}
+            _ => return Err(build_error("env_file"))
+        }
+        match &items["env_files"] {
+            Yaml::Array(files) => {
+                for file in files {
+                    let file = file.as_str().ok_or(build_error("env_files"))?;
+                    result.layers.push(EnvLayer::File(file.to_string()));
+                }
+            }
+            Yaml::BadValue => {}
+            _ => return Err(build_error("env_files"))
+        }
+        match &items["env"] {
+            Yaml::Hash(env) => {
+                let mut values = Vec::new();
+                for (key, value) in env {
+                    let key = key.as_str().ok_or(build_error("env"))?;
+                    values.push((key.to_string(), yaml_to_string(value).ok_or(build_error("env"))?));
+                }
+                result.layers.push(EnvLayer::Values(values));
+            }
+            Yaml::BadValue => {}
+            _ => return Err(build_error("env"))
+        }
+        Ok(result)
+    }
+
+    /// Env files are read on every command start, so their changes do not need a reload.
+    /// File paths can use $WD (the command working folder).
+    pub fn apply(&self, command: &mut Command, work_dir: &Option<String>) -> Result<(), Error> {
+        if self.clean {
+            command.env_clear();
+        }
+        for layer in &self.layers {
+            match layer {
+                EnvLayer::File(file) => {
+                    let variables: HashMap<String, String> =
+                        parse_env_file(CommandToRun::build_file_path(file, work_dir)?)?;
+                    command.envs(&variables);
+                }
+                EnvLayer::Values(values) => {
+                    command.envs(values.iter().map(|(k, v)| (k, v)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_env_files(&self) -> Vec<&String> {
+        self.layers.iter()
+            .filter_map(|layer| match layer {
+                EnvLayer::File(file) => Some(file),
+                EnvLayer::Values(_) => None
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use yaml_rust::YamlLoader;
+    use crate::environment::Environment;
+
+    #[test]
+    fn test_environment() {
+        let docs = YamlLoader::load_from_str("global:\n  clean_env: true\n  env: {A: global, B: global}\n\
+            service:\n  env: {B: 2}\nscript:\n  env_files: [/nonexistent.env]\n  env: {C: true}\n").unwrap();
+        let environment = Environment::default()
+            .with_level(&docs[0]["global"], "config").unwrap()
+            .with_level(&docs[0]["service"], "service").unwrap();
+        let mut command = Command::new("true");
+        environment.apply(&mut command, &None).unwrap();
+        let envs = command.get_envs()
+            .map(|(k, v)| (k.to_str().unwrap(), v.and_then(|v| v.to_str())))
+            .collect::<Vec<_>>();
+        assert_eq!(envs, vec![("A", Some("global")), ("B", Some("2"))]);
+
+        let script = environment.with_level(&docs[0]["script"], "script").unwrap();
+        assert_eq!(script.get_env_files(), vec!["/nonexistent.env"]);
+        assert!(script.apply(&mut Command::new("true"), &None).is_err());
+        let invalid = YamlLoader::load_from_str("env: [A]").unwrap();
+        assert_eq!(Environment::default().with_level(&invalid[0], "a.b").unwrap_err().to_string(), "env is invalid in a.b");
+    }
+}
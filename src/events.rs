@@ -0,0 +1,143 @@
+use std::fmt::{Display, Formatter};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use serde_json::{json, Map, Value};
+use crate::script_state::{exit_status_json, format_exit_status, ScriptState};
+use crate::utilities::format_time;
+
+/// Event stream subscribers by the subscription id, dropped subscriptions remove themselves.
+static SUBSCRIBERS: Mutex<Vec<(usize, Sender<Event>)>> = Mutex::new(Vec::new());
+static NEXT_SUBSCRIPTION_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone)]
+pub enum EventKind {
+    State(ScriptState),
+    Exited(ExitStatus),
+    WaitingForPort(String, u16),
+    Restarted(usize),
+    PostStopScript,
+}
+
+#[derive(Clone)]
+pub struct Event {
+    time: SystemTime,
+    /// script full name, service name for the service events
+    source: String,
+    kind: EventKind,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::State(state) => state.name(),
+            EventKind::Exited(_) => "exited",
+            EventKind::WaitingForPort(..) => "waiting_for_port",
+            EventKind::Restarted(_) => "restarted",
+            EventKind::PostStopScript => "post_stop_script",
+        }
+    }
+}
+
+impl Event {
+    pub fn new(source: &str, kind: EventKind) -> Event {
+        Event { time: SystemTime::now(), source: source.to_string(), kind }
+    }
+
+    pub fn matches(&self, names: &[String]) -> bool {
+        names.is_empty() || names.iter()
+            .any(|n| self.source == *n || self.source.strip_prefix(n.as_str()).is_some_and(|s| s.starts_with('.')))
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("time".to_string(), json!(format_time(self.time)));
+        object.insert("script".to_string(), json!(self.source));
+        object.insert("event".to_string(), json!(self.kind.name()));
+        match &self.kind {
+            EventKind::Exited(status) => { object.insert("exit".to_string(), exit_status_json(status)); }
+            EventKind::WaitingForPort(host, port) => { object.insert("port".to_string(), json!(format!("{}:{}", host, port))); }
+            EventKind::Restarted(restarts) => { object.insert("restarts".to_string(), json!(restarts)); }
+            _ => {}
+        }
+        Value::Object(object)
+    }
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", format_time(self.time), self.source, self.kind.name())?;
+        match &self.kind {
+            EventKind::Exited(status) => write!(f, " ({})", format_exit_status(status)),
+            EventKind::WaitingForPort(host, port) => write!(f, " {}:{}", host, port),
+            EventKind::Restarted(restarts) => write!(f, " (restarts {})", restarts),
+            _ => Ok(())
+        }
+    }
+}
+
+pub struct Subscription {
+    id: usize,
+    receiver: Receiver<Event>,
+}
+
+impl Subscription {
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Event, RecvTimeoutError> {
+        self.receiver.recv_timeout(timeout)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        SUBSCRIBERS.lock().unwrap().retain(|(id, _)| *id != self.id);
+    }
+}
+
+pub fn subscribe() -> Subscription {
+    let (tx, receiver) = channel();
+    let id = NEXT_SUBSCRIPTION_ID.fetch_add(1, Ordering::Relaxed);
+    SUBSCRIBERS.lock().unwrap().push((id, tx));
+    Subscription { id, receiver }
+}
+
+pub fn publish(source: &str, kind: EventKind) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    if subscribers.is_empty() {
+        return;
+    }
+    let event = Event::new(source, kind);
+    subscribers.retain(|(_, s)| s.send(event.clone()).is_ok());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::time::Duration;
+    use crate::events::{Event, EventKind, publish, subscribe, SUBSCRIBERS};
+    use crate::script_state::ScriptState;
+
+    #[test]
+    fn test_events() {
+        let event = Event::new("a.b", EventKind::Exited(ExitStatus::from_raw(3 << 8)));
+        assert!(event.to_string().ends_with(" a.b exited (exit code 3)"));
+        assert_eq!(event.to_json()["exit"]["code"], 3);
+        assert!(event.matches(&[]) && event.matches(&["a".to_string()]) && event.matches(&["a.b".to_string()]));
+        assert!(!event.matches(&["a.bc".to_string()]) && !event.matches(&["ab".to_string()]));
+
+        // other tests publish to the same bus, only the events of a.b are checked
+        let subscription = subscribe();
+        let id = subscription.id;
+        publish("a.b", EventKind::State(ScriptState::Running));
+        let received = iter::from_fn(|| subscription.recv_timeout(Duration::from_millis(100)).ok())
+            .filter(|e| e.source == "a.b")
+            .map(|e| e.kind.name())
+            .collect::<Vec<_>>();
+        assert_eq!(received, vec!["running"]);
+        drop(subscription);
+        assert!(!SUBSCRIBERS.lock().unwrap().iter().any(|(i, _)| *i == id));
+    }
+}
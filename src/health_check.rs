@@ -0,0 +1,332 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use regex::Regex;
+use yaml_rust::Yaml;
+use crate::command_to_run::CommandToRun;
+use crate::script::process_host_port;
+use crate::script_state::ScriptState;
+use crate::utilities::build_invalid_data_error_string;
+
+const DEFAULT_INTERVAL: u64 = 1;
+const DEFAULT_TIMEOUT: u64 = 5;
+const DEFAULT_RETRIES: u64 = 60;
+const DEFAULT_FAILURE_THRESHOLD: u64 = 3;
+
+pub enum Probe {
+    Http { host: String, port: u16, path: String, expected_status: Option<u16>, expected_body: Option<String> },
+    Tcp { host: String, port: u16 },
+    Command(CommandToRun),
+    LogPattern { log_file: String, pattern: Regex, offset: Arc<AtomicU64> },
+}
+
+pub struct HealthCheck {
+    probe: Probe,
+    interval: Duration,
+    timeout: Duration,
+    retries: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LivenessAction {
+    Restart,
+    Stop,
+    Report,
+}
+
+impl LivenessAction {
+    pub fn stops_script(&self) -> bool {
+        *self != LivenessAction::Report
+    }
+
+    /// Returns the state the stopped run ends with, a failed run is restarted by the restart policy.
+    pub fn get_failure_state(&self) -> Option<ScriptState> {
+        match self {
+            LivenessAction::Restart => Some(ScriptState::Failed),
+            LivenessAction::Stop | LivenessAction::Report => None
+        }
+    }
+}
+
+pub struct LivenessCheck {
+    check: HealthCheck,
+    failure_threshold: usize,
+    action: LivenessAction,
+}
+
+fn parse_number(item: &Yaml, default: u64, key: &str, name: &String) -> Result<u64, Error> {
+    match item {
+        Yaml::BadValue => Ok(default),
+        Yaml::Integer(v) if *v > 0 => Ok(*v as u64),
+        _ => Err(build_invalid_data_error_string(format!("{} is invalid in script {}", key, name)))
+    }
+}
+
+/// Parses http://host[:port][/path] url.
+fn parse_url(url: &str, name: &String) -> Result<(String, u16, String), Error> {
+    let rest = url.strip_prefix("http://")
+        .ok_or(build_invalid_data_error_string(format!("only http urls are supported in script {}", name)))?;
+    let (host_port, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], rest[idx..].to_string()),
+        None => (rest, "/".to_string())
+    };
+    let (host, port) = match host_port.split_once(':') {
+        Some((h, p)) => (h, u16::from_str(p)
+            .map_err(|_e| build_invalid_data_error_string(format!("url port is invalid in script {}", name)))?),
+        None => (host_port, 80)
+    };
+    if host.is_empty() {
+        return Err(build_invalid_data_error_string(format!("url host is invalid in script {}", name)));
+    }
+    Ok((host.to_string(), port, path))
+}
+
+fn connect(host: &String, port: u16, timeout: Duration) -> Result<TcpStream, Error> {
+    let mut last_error = Error::new(ErrorKind::NotFound, format!("could not resolve {}", host));
+    for address in (host.as_str(), port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e
+        }
+    }
+    Err(last_error)
+}
+
+fn http_get(host: &String, port: u16, path: &String, timeout: Duration) -> Result<(u16, String), Error> {
+    let mut stream = connect(host, port, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    write!(stream, "GET {} HTTP/1.0\r\nHost: {}:{}\r\nConnection: close\r\n\r\n", path, host, port)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response).to_string();
+    let status = response.split(' ').nth(1)
+        .and_then(|s| u16::from_str(s).ok())
+        .ok_or(Error::new(ErrorKind::InvalidData, "invalid http response"))?;
+    let body = response.split_once("\r\n\r\n").map(|(_h, b)| b.to_string()).unwrap_or_default();
+    Ok((status, body))
+}
+
+impl Probe {
+    fn new(item: &Yaml, name: &String, command: &CommandToRun) -> Result<Probe, Error> {
+        if let Some(url) = item["http"].as_str() {
+            let (host, port, path) = parse_url(url, name)?;
+            let expected_status = match &item["expected_status"] {
+                Yaml::BadValue => None,
+                Yaml::Integer(s) if *s >= 100 && *s < 600 => Some(*s as u16),
+                _ => return Err(build_invalid_data_error_string(
+                    format!("expected_status is invalid in script {}", name)))
+            };
+            let expected_body = item["expected_body"].as_str().map(|s| s.to_string());
+            return Ok(Probe::Http { host, port, path, expected_status, expected_body });
+        }
+        match &item["tcp"] {
+            Yaml::String(host_port) => {
+                let (host, port) = process_host_port(host_port, name)?;
+                return Ok(Probe::Tcp { host, port });
+            }
+            Yaml::Integer(port) => {
+                let (host, port) = process_host_port(port.to_string().as_str(), name)?;
+                return Ok(Probe::Tcp { host, port });
+            }
+            _ => {}
+        }
+        if let Some(c) = item["command"].as_str() {
+            return Ok(Probe::Command(CommandToRun::new(c.to_string(), None, None,
+                                                       command.get_work_dir().clone(),
+                                                       command.get_env().clone())?));
+        }
+        if let Some(p) = item["log_pattern"].as_str() {
+            let pattern = Regex::new(p)
+                .map_err(|e| build_invalid_data_error_string(
+                    format!("log_pattern is invalid in script {}: {}", name, e)))?;
+            let log_file = match item["log_file"].as_str() {
+                Some(f) => CommandToRun::build_file_path(&f.to_string(), command.get_work_dir())?,
+                None => command.get_log_file_out().clone()
+                    .ok_or(build_invalid_data_error_string(
+                        format!("log_pattern requires log_file in script {}", name)))?
+            };
+            return Ok(Probe::LogPattern { log_file, pattern, offset: command.get_log_offset() });
+        }
+        Err(build_invalid_data_error_string(format!("health check has no probe in script {}", name)))
+    }
+
+    fn check(&self, timeout: Duration) -> Result<(), Error> {
+        match self {
+            Probe::Http { host, port, path, expected_status, expected_body } => {
+                let (status, body) = http_get(host, *port, path, timeout)?;
+                let status_ok = match expected_status {
+                    Some(s) => status == *s,
+                    None => (200..300).contains(&status)
+                };
+                if !status_ok {
+                    return Err(Error::other(format!("unexpected http status {}", status)));
+                }
+                if let Some(b) = expected_body {
+                    if !body.contains(b.as_str()) {
+                        return Err(Error::other("expected body not found"));
+                    }
+                }
+                Ok(())
+            }
+            Probe::Tcp { host, port } => connect(host, *port, timeout).map(|_s| ()),
+            Probe::Command(command) => {
+                if command.run_with_timeout(timeout)? {
+                    Ok(())
+                } else {
+                    Err(Error::other("health check command failed"))
+                }
+            }
+            Probe::LogPattern { log_file, pattern, offset } => {
+                let contents = fs::read(log_file)?;
+                let offset = offset.load(Ordering::Relaxed) as usize;
+                let contents = contents.get(offset..).unwrap_or(&contents);
+                if pattern.is_match(String::from_utf8_lossy(contents).as_ref()) {
+                    Ok(())
+                } else {
+                    Err(Error::other("log pattern not found"))
+                }
+            }
+        }
+    }
+}
+
+impl HealthCheck {
+    pub fn new(item: &Yaml, name: &String, command: &CommandToRun) -> Result<Option<HealthCheck>, Error> {
+        if item.is_badvalue() {
+            return Ok(None);
+        }
+        if item.as_hash().is_none() {
+            return Err(build_invalid_data_error_string(format!("health check is invalid in script {}", name)));
+        }
+        Ok(Some(HealthCheck {
+            probe: Probe::new(item, name, command)?,
+            interval: Duration::from_secs(parse_number(&item["interval"], DEFAULT_INTERVAL, "interval", name)?),
+            timeout: Duration::from_secs(parse_number(&item["timeout"], DEFAULT_TIMEOUT, "timeout", name)?),
+            retries: parse_number(&item["retries"], DEFAULT_RETRIES, "retries", name)? as usize,
+        }))
+    }
+
+    pub fn check(&self) -> Result<(), Error> {
+        let started = Instant::now();
+        let result = self.probe.check(self.timeout);
+        if result.is_ok() && started.elapsed() > self.timeout {
+            return Err(Error::new(ErrorKind::TimedOut, "health check timed out"));
+        }
+        result
+    }
+
+    pub fn get_interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn get_retries(&self) -> usize {
+        self.retries
+    }
+}
+
+impl LivenessCheck {
+    pub fn new(item: &Yaml, name: &String, command: &CommandToRun) -> Result<Option<LivenessCheck>, Error> {
+        let check = match HealthCheck::new(item, name, command)? {
+            Some(c) => c,
+            None => return Ok(None)
+        };
+        let failure_threshold = parse_number(&item["failure_threshold"], DEFAULT_FAILURE_THRESHOLD,
+                                             "failure_threshold", name)? as usize;
+        let action = match item["action"].as_str() {
+            None | Some("restart") => LivenessAction::Restart,
+            Some("stop") => LivenessAction::Stop,
+            Some("report") => LivenessAction::Report,
+            _ => return Err(build_invalid_data_error_string(
+                format!("liveness action is invalid in script {}", name)))
+        };
+        Ok(Some(LivenessCheck { check, failure_threshold, action }))
+    }
+
+    pub fn check(&self) -> Result<(), Error> {
+        self.check.check()
+    }
+
+    pub fn get_interval(&self) -> Duration {
+        self.check.get_interval()
+    }
+
+    pub fn get_failure_threshold(&self) -> usize {
+        self.failure_threshold
+    }
+
+    pub fn get_action(&self) -> LivenessAction {
+        self.action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use yaml_rust::{Yaml, YamlLoader};
+    use crate::command_to_run::CommandToRun;
+    use crate::environment::Environment;
+    use crate::health_check::{parse_url, HealthCheck, LivenessAction, LivenessCheck};
+    use crate::script_state::ScriptState;
+
+    #[test]
+    fn test_parse_url() {
+        let name = "test".to_string();
+        assert_eq!(parse_url("http://localhost:8080/health", &name).unwrap(),
+                   ("localhost".to_string(), 8080, "/health".to_string()));
+        assert_eq!(parse_url("http://server", &name).unwrap(), ("server".to_string(), 80, "/".to_string()));
+        assert!(parse_url("https://server/health", &name).is_err());
+        assert!(parse_url("http://server:abc/health", &name).is_err());
+        assert!(parse_url("http://:8080/health", &name).is_err());
+    }
+
+    #[test]
+    fn test_liveness_check() {
+        let name = "test".to_string();
+        let command = CommandToRun::new("/bin/true".to_string(), None, None, None, Environment::default()).unwrap();
+        let parse = |text: &str| {
+            let docs = YamlLoader::load_from_str(text).unwrap();
+            LivenessCheck::new(&docs[0], &name, &command)
+        };
+        let check = parse("tcp: localhost:8080").unwrap().unwrap();
+        assert_eq!(check.get_failure_threshold(), 3);
+        assert_eq!(check.get_action(), LivenessAction::Restart);
+        let check = parse("tcp: localhost:8080\nfailure_threshold: 5\naction: report").unwrap().unwrap();
+        assert_eq!(check.get_failure_threshold(), 5);
+        assert_eq!(check.get_action(), LivenessAction::Report);
+        assert_eq!(parse("tcp: localhost:8080\naction: stop").unwrap().unwrap().get_action(), LivenessAction::Stop);
+        assert_eq!(parse("tcp: localhost:8080\naction: kill").err().unwrap().to_string(),
+                   "liveness action is invalid in script test");
+        assert!(parse("tcp: localhost:8080\nfailure_threshold: 0").is_err());
+        assert!(parse("failure_threshold: 5").is_err());
+        assert!(LivenessCheck::new(&Yaml::BadValue, &name, &command).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_liveness_action() {
+        assert!(LivenessAction::Restart.stops_script());
+        assert_eq!(LivenessAction::Restart.get_failure_state(), Some(ScriptState::Failed));
+        assert!(LivenessAction::Stop.stops_script());
+        assert_eq!(LivenessAction::Stop.get_failure_state(), None);
+        assert!(!LivenessAction::Report.stops_script());
+    }
+
+    #[test]
+    fn test_log_pattern_current_run() {
+        let file_name = std::env::temp_dir().join(format!("runner_health_test_{}.log", std::process::id()))
+            .to_string_lossy().to_string();
+        fs::write(&file_name, "ready\n").unwrap();
+        let command = CommandToRun::new("/bin/echo started".to_string(), Some(file_name.clone()), None, None,
+                                        Environment::default()).unwrap();
+        let docs = YamlLoader::load_from_str("log_pattern: ready").unwrap();
+        let check = HealthCheck::new(&docs[0], &"test".to_string(), &command).unwrap().unwrap();
+        assert!(check.check().is_ok());
+        command.run_async(false, true).unwrap().unwrap().wait().unwrap();
+        assert_eq!(check.check().err().unwrap().to_string(), "log pattern not found");
+        fs::remove_file(&file_name).unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::process::exit;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use serde_json::Value;
+use tiny_http::{Header, Request, Response, Server};
+use crate::auth::{check_token, generate_token};
+use crate::command_result::OutputFormat;
+use crate::metrics::render_metrics;
+use crate::script::StatusFormat;
+use crate::service_manager::ServiceManager;
+use crate::user_command::WriterWithTCP;
+use crate::utilities::build_invalid_data_error_string;
+
+const DEFAULT_LOG_LINES: usize = 100;
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+struct HttpContext {
+    manager: &'static ServiceManager,
+    noexec: bool,
+    token: Option<String>,
+    allowed_hosts: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Route {
+    Dashboard,
+    Metrics,
+    Sets,
+    Status(Option<String>, StatusFormat),
+    Start(String),
+    Stop(String),
+    Restart(String),
+    Up(String),
+    Logs(String, usize),
+    Shutdown,
+}
+
+impl Route {
+    fn get_command(&self) -> Vec<String> {
+        let parts = match self {
+            Route::Dashboard => vec!["dashboard"],
+            Route::Metrics => vec!["metrics"],
+            Route::Sets => vec!["sets"],
+            Route::Status(Some(name), _) => vec!["status", name],
+            Route::Status(None, _) => vec!["status"],
+            Route::Start(name) => vec!["start", name],
+            Route::Stop(name) => vec!["stop", name],
+            Route::Restart(name) => vec!["restart", name],
+            Route::Up(name) => vec!["up", name],
+            Route::Logs(name, _) => vec!["logs", name],
+            Route::Shutdown => vec!["shutdown"],
+        };
+        parts.into_iter().map(|p| p.to_string()).collect()
+    }
+}
+
+fn parse_query(query: &str) -> HashMap<&str, &str> {
+    query.split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| p.split_once('=').unwrap_or((p, "")))
+        .collect()
+}
+
+fn parse_route(method: &str, url: &str) -> Result<Route, Error> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let query = parse_query(query);
+    let parts = path.split('/').filter(|p| !p.is_empty()).collect::<Vec<_>>();
+    let name = |i: usize| parts[i].to_string();
+    match (method, parts.as_slice()) {
+        ("GET", []) => Ok(Route::Dashboard),
+        ("GET", ["metrics"]) => Ok(Route::Metrics),
+        ("GET", ["sets"]) => Ok(Route::Sets),
+        ("GET", ["services"]) | ("GET", ["services", _]) => {
+            let format = match query.get("format") {
+                None | Some(&"short") => StatusFormat::Short,
+                Some(&"long") => StatusFormat::Long,
+                Some(&"history") => StatusFormat::History,
+                Some(f) => return Err(Error::new(ErrorKind::InvalidInput, format!("unknown status format {}", f)))
+            };
+            Ok(Route::Status(parts.get(1).map(|n| n.to_string()), format))
+        }
+        ("POST", ["services", _, "start"]) => Ok(Route::Start(name(1))),
+        ("POST", ["services", _, "stop"]) => Ok(Route::Stop(name(1))),
+        ("POST", ["services", _, "restart"]) => Ok(Route::Restart(name(1))),
+        ("POST", ["sets", _, "up"]) => Ok(Route::Up(name(1))),
+        ("GET", ["scripts", _, "logs"]) => {
+            let lines = match query.get("lines") {
+                Some(l) => usize::from_str(l)
+                    .map_err(|_e| Error::new(ErrorKind::InvalidInput, "invalid lines value"))?,
+                None => DEFAULT_LOG_LINES
+            };
+            Ok(Route::Logs(name(1), lines))
+        }
+        ("POST", ["shutdown"]) => Ok(Route::Shutdown),
+        _ => Err(Error::new(ErrorKind::NotFound, format!("unknown request {} {}", method, path)))
+    }
+}
+
+fn get_status_code(result: &Value) -> u16 {
+    match result["error"]["code"].as_str() {
+        None => 200,
+        Some("not_found") => 404,
+        Some("invalid_input") | Some("invalid_data") => 400,
+        Some("unauthorized") => 401,
+        Some("timed_out") => 504,
+        Some(_) => 500
+    }
+}
+
+fn get_header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request.headers().iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+fn get_bearer_token(request: &Request) -> Option<String> {
+    get_header(request, "Authorization")
+        .and_then(|v| v.strip_prefix("Bearer ").map(|t| t.trim().to_string()))
+}
+
+/// Host header values the api answers to, any host is allowed when it listens on all the interfaces.
+fn get_allowed_hosts(address: Option<SocketAddr>) -> Vec<String> {
+    let address = match address {
+        Some(a) if !a.ip().is_unspecified() => a,
+        _ => return Vec::new()
+    };
+    let mut hosts = vec![address.to_string()];
+    if address.ip().is_loopback() {
+        hosts.push(format!("localhost:{}", address.port()));
+    }
+    if address.port() == 80 {
+        hosts = hosts.iter().flat_map(|h| [h.clone(), h.strip_suffix(":80").unwrap().to_string()]).collect();
+    }
+    hosts
+}
+
+/// The host check rejects DNS rebinding, the origin check rejects POST requests from other sites.
+fn check_origin(method: &str, host: Option<&str>, origin: Option<&str>, allowed_hosts: &[String]) -> Result<(), Error> {
+    let host = host.ok_or(Error::new(ErrorKind::PermissionDenied, "host header is required"))?;
+    if !allowed_hosts.is_empty() && !allowed_hosts.iter().any(|h| h == host) {
+        return Err(Error::new(ErrorKind::PermissionDenied, format!("host {} is not allowed", host)));
+    }
+    match origin {
+        Some(origin) if method != "GET" && origin != format!("http://{}", host) =>
+            Err(Error::new(ErrorKind::PermissionDenied, format!("origin {} is not allowed", origin))),
+        _ => Ok(())
+    }
+}
+
+fn execute(route: &Route, manager: &'static ServiceManager, noexec: bool,
+           writer: &mut WriterWithTCP) -> Result<Option<Value>, Error> {
+    match route {
+        Route::Dashboard | Route::Metrics => Ok(None),
+        Route::Sets => Ok(Some(manager.report_service_sets_json())),
+        Route::Status(name, format) => {
+            let names = name.iter().collect::<Vec<_>>();
+            manager.report_status_json(&names, *format).map(Some)
+        }
+        Route::Start(name) => manager.start_service(false, name, noexec, writer).map(|_| None),
+        Route::Stop(name) => manager.stop_service(name, noexec, writer).map(|_| None),
+        Route::Restart(name) => manager.restart(std::slice::from_ref(name), noexec, writer).map(|_| None),
+        Route::Up(name) => manager.up(name, noexec, writer).map(|_| None),
+        Route::Logs(name, lines) => manager.read_script_logs(name, *lines).map(Some),
+        Route::Shutdown => manager.shutdown(noexec, writer).map(|_| None),
+    }
+}
+
+fn respond(request: Request, result: &Value) {
+    let status_code = get_status_code(result);
+    respond_with_status(request, result, status_code)
+}
+
+fn respond_with_status(request: Request, result: &Value, status_code: u16) {
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let response = Response::from_string(result.to_string())
+        .with_status_code(status_code)
+        .with_header(header);
+    if let Err(e) = request.respond(response) {
+        println!("HTTP response error {}", e);
+    }
+}
+
+fn respond_text(request: Request, text: String, content_type: &str) {
+    let header = Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap();
+    if let Err(e) = request.respond(Response::from_string(text).with_header(header)) {
+        println!("HTTP response error {}", e);
+    }
+}
+
+fn reject(request: Request, writer: &mut WriterWithTCP, error: Error, status_code: u16) {
+    let peer = request.remote_addr().map(|a| a.to_string()).unwrap_or("unknown".to_string());
+    println!("Rejected HTTP request from {}: {}", peer, error);
+    let result = writer.take_result(&[], &Err(error));
+    respond_with_status(request, &result, status_code)
+}
+
+/// Every response except the dashboard page is the command result object, the same as in json output format.
+/// The dashboard page contains no data, so it is served without the token.
+fn handle_request(request: Request, context: &HttpContext) {
+    let (manager, noexec) = (context.manager, context.noexec);
+    let mut writer = WriterWithTCP::new(None, OutputFormat::Json, 0);
+    let method = request.method().as_str().to_string();
+    if let Err(e) = check_origin(&method, get_header(&request, "Host"), get_header(&request, "Origin"),
+                                 &context.allowed_hosts) {
+        return reject(request, &mut writer, e, 403);
+    }
+    let route = parse_route(&method, request.url());
+    if route.as_ref().is_ok_and(|r| *r == Route::Dashboard) {
+        return respond_text(request, DASHBOARD.to_string(), "text/html; charset=utf-8");
+    }
+    if let Err(e) = check_token(&context.token, &get_bearer_token(&request)) {
+        return reject(request, &mut writer, e, 401);
+    }
+    if route.as_ref().is_ok_and(|r| *r == Route::Metrics) {
+        return match manager.report_status_json(&[], StatusFormat::Metrics) {
+            Ok(status) => respond_text(request, render_metrics(&status), "text/plain; version=0.0.4"),
+            Err(e) => respond(request, &writer.take_result(&[], &Err(e)))
+        };
+    }
+    let command = route.as_ref().map(|r| r.get_command()).unwrap_or_default();
+    let result = route.and_then(|r| execute(&r, manager, noexec, &mut writer));
+    let result_object = writer.take_result(&command, &result);
+    respond(request, &result_object);
+    if result.is_ok() && command.first().is_some_and(|c| c == "shutdown") {
+        exit(0);
+    }
+}
+
+/// Binds before the startup commands run, so the wrong address fails early.
+/// The generated token protects only the loopback address, other addresses require the configured token.
+pub fn http_bind(address: &str, token: &Option<String>) -> Result<Server, Error> {
+    let server = Server::http(address)
+        .map_err(|e| Error::other(format!("could not bind http api to {}: {}", address, e)))?;
+    if token.is_none() && !server.server_addr().to_ip().is_some_and(|a| a.ip().is_loopback()) {
+        return Err(build_invalid_data_error_string(
+            format!("http api on {} requires token_file or token_env", address)));
+    }
+    Ok(server)
+}
+
+/// Every request is served in its own thread because commands like start can take long time.
+/// Without the configured token a random one is generated and printed only, the dashboard reads it
+/// from the url fragment, which the browser does not send to the server.
+pub fn http_start(server: Server, manager: &'static ServiceManager, noexec: bool,
+                  token: Option<String>) -> Result<(), Error> {
+    println!("HTTP API listening on {}", server.server_addr());
+    let token = match token {
+        Some(token) => token,
+        None => {
+            let token = generate_token()?;
+            println!("HTTP API token: {}, dashboard: http://{}/#token={}", token, server.server_addr(), token);
+            token
+        }
+    };
+    let allowed_hosts = get_allowed_hosts(server.server_addr().to_ip());
+    let context = Arc::new(HttpContext { manager, noexec, token: Some(token), allowed_hosts });
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let context = context.clone();
+            thread::spawn(move || handle_request(request, &context));
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http_api::{check_origin, get_allowed_hosts, parse_route, Route};
+    use crate::script::StatusFormat;
+
+    #[test]
+    fn test_parse_route() {
+        assert_eq!(parse_route("GET", "/").unwrap(), Route::Dashboard);
+        assert_eq!(parse_route("GET", "/sets").unwrap(), Route::Sets);
+        assert_eq!(parse_route("GET", "/metrics").unwrap(), Route::Metrics);
+        assert_eq!(parse_route("GET", "/services").unwrap(), Route::Status(None, StatusFormat::Short));
+        assert_eq!(parse_route("GET", "/services/a?format=long").unwrap(),
+                   Route::Status(Some("a".to_string()), StatusFormat::Long));
+        assert_eq!(parse_route("POST", "/services/a/restart").unwrap(), Route::Restart("a".to_string()));
+        assert_eq!(parse_route("POST", "/sets/all/up").unwrap(), Route::Up("all".to_string()));
+        assert_eq!(parse_route("GET", "/scripts/a.b/logs?lines=5").unwrap(), Route::Logs("a.b".to_string(), 5));
+        assert_eq!(parse_route("POST", "/shutdown").unwrap(), Route::Shutdown);
+        assert!(parse_route("GET", "/services?format=wide").is_err());
+        assert!(parse_route("GET", "/services/a/start").is_err());
+        assert!(parse_route("POST", "/unknown").is_err());
+    }
+
+    #[test]
+    fn test_check_origin() {
+        let hosts = get_allowed_hosts(Some("127.0.0.1:8080".parse().unwrap()));
+        assert_eq!(hosts, vec!["127.0.0.1:8080".to_string(), "localhost:8080".to_string()]);
+        assert!(get_allowed_hosts(Some("0.0.0.0:8080".parse().unwrap())).is_empty());
+        assert!(check_origin("GET", Some("localhost:8080"), None, &hosts).is_ok());
+        assert!(check_origin("POST", Some("127.0.0.1:8080"), Some("http://127.0.0.1:8080"), &hosts).is_ok());
+        assert!(check_origin("POST", Some("localhost:8080"), None, &hosts).is_ok());
+        assert!(check_origin("GET", Some("evil.com:8080"), None, &hosts).is_err());
+        assert!(check_origin("GET", None, None, &hosts).is_err());
+        assert!(check_origin("POST", Some("localhost:8080"), Some("http://evil.com"), &hosts).is_err());
+        assert!(check_origin("GET", Some("localhost:8080"), Some("http://evil.com"), &hosts).is_ok());
+        assert!(check_origin("POST", Some("server:8080"), Some("http://server:8080"), &[]).is_ok());
+    }
+}
@@ -5,23 +5,51 @@ mod user_command;
 mod server;
 mod command_to_run;
 mod utilities;
+mod restart_policy;
+mod signals;
+mod dependency_graph;
+mod health_check;
+mod script_state;
+mod process_stats;
+mod command_result;
+mod control_stream;
+mod auth;
+mod protocol;
+mod http_api;
+mod events;
+mod metrics;
+mod repl;
+mod config;
+mod variables;
+mod environment;
 
-use std::fs;
 use std::env::args;
 use std::io::{Error, ErrorKind};
 use std::process::exit;
 use std::str::FromStr;
-use yaml_rust::YamlLoader;
 use ctrlc;
-use crate::server::{send_command_to_server, server_start};
+use crate::auth::{DEFAULT_TOKEN_ENV, load_token};
+use crate::command_result::OutputFormat;
+use crate::config::{load_config, watch_config};
+use crate::control_stream::{ControlSettings, parse_socket_mode};
+use crate::http_api::{http_bind, http_start};
+use crate::repl::start_repl;
+use crate::server::{run_client, server_start};
 use crate::service_manager::ServiceManager;
 use crate::user_command::{run_user_commands, WriterWithTCP};
-use crate::utilities::{build_invalid_data_error_str, build_invalid_data_error_string};
+use crate::utilities::build_invalid_data_error_string;
 
 static mut MANAGER: Option<ServiceManager> = None;
 
+const VALUE_OPTIONS: [&str; 10] = ["port", "host", "socket", "socket_mode", "token_file", "token_env",
+    "http_port", "http_host", "format", "config"];
+
 fn usage() {
-    println!("Usage: runner [config_file_name] [commands]")
+    println!("Usage: runner [config_file_name] [noinit] [noexec] [norepl] [watch_config] [socket PATH] [socket_mode MODE] [port N] [host ADDRESS]
+              [token_file PATH] [token_env NAME] [http_port N] [http_host ADDRESS] [--json|format text|json] [commands]
+Client: runner [config PATH] [socket PATH] [port N] [host ADDRESS] [token_file PATH] [token_env NAME] [--json|format text|json] commands
+  config reads the control settings from the server configuration file, the command line options override them
+Client exit codes: 0 success, 1 failed, 2 invalid command or data, 3 not found, 4 timed out")
 }
 
 fn parse_port(port: &String) -> Result<isize, Error> {
@@ -42,19 +70,39 @@ fn main() -> Result<(), Error> {
     let mut n = 0;
     let mut noinit = false;
     let mut noexec = false;
-    let mut nextport = false;
-    let mut port = 65000;
+    let mut norepl = false;
+    let mut watch = false;
+    let mut option = None;
+    let mut control = ControlSettings::default();
+    let mut format = None;
+    let mut client_config = None;
     for arg in args() {
-        if nextport {
-            nextport = false;
-            port = parse_port(&arg)?;
+        if let Some(name) = option.take() {
+            match name {
+                "port" => control.port = Some(parse_port(&arg)? as u16),
+                "host" => control.host = Some(arg),
+                "socket" => control.socket = Some(arg),
+                "socket_mode" => control.socket_mode = Some(parse_socket_mode(&arg)?),
+                "token_file" => control.token_file = Some(arg),
+                "token_env" => control.token_env = Some(arg),
+                "http_port" => control.http_port = Some(parse_port(&arg)? as u16),
+                "http_host" => control.http_host = Some(arg),
+                "config" => client_config = Some(arg),
+                _ => format = Some(OutputFormat::from_name(&arg)?)
+            }
         } else if n != 0 {
             if arg == "noinit" {
                 noinit = true;
             } else if arg == "noexec" {
                 noexec = true;
-            } else if arg == "port" {
-                nextport = true;
+            } else if arg == "norepl" {
+                norepl = true;
+            } else if arg == "watch_config" {
+                watch = true;
+            } else if let Some(name) = VALUE_OPTIONS.iter().find(|o| **o == arg) {
+                option = Some(*name);
+            } else if arg == "--json" {
+                format = Some(OutputFormat::Json);
             } else if n == 1 && arg.ends_with(".yml") {
                 config_file = Some(arg);
             } else {
@@ -63,41 +111,64 @@ fn main() -> Result<(), Error> {
         }
         n += 1;
     }
-    if nextport || (config_file.is_none() && commands.len() == 0) {
+    if option.is_some() || (config_file.is_none() && commands.len() == 0) {
         usage();
         return Ok(());
     }
     if let Some(config) = config_file {
-        let contents = fs::read_to_string(config)?;
-        let docs = YamlLoader::load_from_str(contents.as_str())
-            .map_err(|e|build_invalid_data_error_string(e.to_string()))?;
-        let doc = &docs[0];
-        let services = doc["services"].as_hash()
-            .ok_or(build_invalid_data_error_str("could not find any service"))?;
-        let service_sets = doc["service-sets"].as_hash()
-            .ok_or(build_invalid_data_error_str("could not find any service"))?;
+        let doc = &load_config(&config)?;
         let init_command = if noinit { None } else { doc["init-command"].as_str().map(|s| s.to_string()) };
         let shutdown_command = if noinit { None } else { doc["shutdown-command"].as_str().map(|s| s.to_string()) };
+        let watch = watch || doc["watch-config"].as_bool().unwrap_or(false);
+        let control = ControlSettings::from_yaml(&doc["control"])?.merge(control);
+        let address = control.get_address();
+        let token = load_token(&control.token_file, &control.token_env)?;
 
-        let manager = ServiceManager::new(service_sets, services, init_command,
+        let manager = ServiceManager::new(doc, Some(config.clone()), init_command,
                                           shutdown_command, noexec)?;
+        // binding before running the commands fails early when another server is running
+        let listener = address.bind()?;
+        let http_server = match control.get_http_address() {
+            Some(http_address) => Some(http_bind(&http_address, &token)?),
+            None => None
+        };
         unsafe {
             MANAGER = Some(manager);
 
+            let format = format.unwrap_or(OutputFormat::Text);
             if commands.len() > 0 {
-                run_user_commands(commands, MANAGER.as_ref().unwrap(), noexec, WriterWithTCP::new(None));
+                run_user_commands(commands, MANAGER.as_ref().unwrap(), noexec, WriterWithTCP::new(None, format, 0));
             }
 
             if noexec {
-                ctrlc::set_handler(|| {shutdown(true, WriterWithTCP::new(None))})
+                ctrlc::set_handler(|| {shutdown(true, WriterWithTCP::new(None, OutputFormat::Text, 0))})
             } else {
-                ctrlc::set_handler(|| {shutdown(false, WriterWithTCP::new(None))})
+                ctrlc::set_handler(|| {shutdown(false, WriterWithTCP::new(None, OutputFormat::Text, 0))})
             }.map_err(|e|build_invalid_data_error_string(e.to_string()))?;
 
-            return server_start(port as u16, MANAGER.as_ref().unwrap(), noexec);
+            let manager = MANAGER.as_ref().unwrap();
+            if let Some(server) = http_server {
+                http_start(server, manager, noexec, token.clone())?;
+            }
+            if watch {
+                watch_config(config, manager, noexec);
+            }
+            if !norepl {
+                start_repl(manager, noexec);
+            }
+            return server_start(listener, &address, manager, noexec, token);
         }
     }
-    send_command_to_server(port as u16, commands.join(" "))
+    let control = match client_config {
+        Some(config) => ControlSettings::from_yaml(&load_config(&config)?["control"])?.merge(control),
+        None => control
+    };
+    let token = if control.token_file.is_none() && control.token_env.is_none() {
+        std::env::var(DEFAULT_TOKEN_ENV).ok().filter(|t| !t.is_empty())
+    } else {
+        load_token(&control.token_file, &control.token_env)?
+    };
+    run_client(&control.get_address(), commands, format, token)
 }
 
 fn shutdown(noexec: bool, mut writer: WriterWithTCP) {
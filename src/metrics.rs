@@ -0,0 +1,85 @@
+use std::fmt::Write;
+use serde_json::Value;
+use crate::script_state::ALL_STATES;
+
+/// Metric name, type, help and the script status field the value is taken from.
+const SCRIPT_METRICS: [(&str, &str, &str, &str); 7] = [
+    ("runner_script_uptime_seconds", "gauge", "Seconds since the script process was started.", "uptime_seconds"),
+    ("runner_script_restarts", "gauge", "Script restarts made by the restart policy or liveness check.", "restarts"),
+    ("runner_script_cpu_seconds_total", "counter", "CPU time used by the script process group.", "cpu_seconds"),
+    ("runner_script_rss_bytes", "gauge", "Resident memory of the script process group.", "rss_bytes"),
+    ("runner_script_last_exit_code", "gauge", "Exit code of the last script process run.", "last_exit_code"),
+    ("runner_script_start_attempts_total", "counter", "Script process start attempts.", "start_attempts"),
+    ("runner_script_health_check_failures_total", "counter", "Failed health and liveness probes.",
+     "health_check_failures"),
+];
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn get_value(status: &Value, field: &str) -> Option<f64> {
+    match field {
+        "last_exit_code" => status["last_exit"]["code"].as_f64(),
+        // stopped script has no process, so its uptime is zero
+        "uptime_seconds" => Some(status[field].as_f64().unwrap_or(0.0)),
+        _ => status[field].as_f64()
+    }
+}
+
+pub fn render_metrics(status: &Value) -> String {
+    let mut scripts = Vec::new();
+    if let Some(services) = status["services"].as_object() {
+        for (service_name, service) in services {
+            if let Some(service) = service.as_object() {
+                for (script_name, script) in service {
+                    let labels = format!("service=\"{}\",script=\"{}\"", escape_label(service_name),
+                                         escape_label(script_name));
+                    scripts.push((labels, script));
+                }
+            }
+        }
+    }
+    let mut result = String::new();
+    let _ = writeln!(result, "# HELP runner_script_state Current script state, 1 for the current state.");
+    let _ = writeln!(result, "# TYPE runner_script_state gauge");
+    for (labels, script) in &scripts {
+        let current = script["state"].as_str().unwrap_or("");
+        for state in ALL_STATES {
+            let value = if state.name() == current { 1 } else { 0 };
+            let _ = writeln!(result, "runner_script_state{{{},state=\"{}\"}} {}", labels, state.name(), value);
+        }
+    }
+    for (name, metric_type, help, field) in SCRIPT_METRICS {
+        let _ = writeln!(result, "# HELP {} {}", name, help);
+        let _ = writeln!(result, "# TYPE {} {}", name, metric_type);
+        for (labels, script) in &scripts {
+            if let Some(value) = get_value(script, field) {
+                let _ = writeln!(result, "{}{{{}}} {}", name, labels, value);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::metrics::render_metrics;
+
+    #[test]
+    fn test_render_metrics() {
+        let status = json!({"services": {"a": {"b\"c": {"state": "running", "restarts": 2, "uptime_seconds": 5,
+            "last_exit": {"code": 3, "signal": null}, "start_attempts": 3, "health_check_failures": 0,
+            "cpu_seconds": 1.5}}}});
+        let metrics = render_metrics(&status);
+        let labels = "service=\"a\",script=\"b\\\"c\"";
+        assert!(metrics.contains(&format!("runner_script_state{{{},state=\"running\"}} 1\n", labels)));
+        assert!(metrics.contains(&format!("runner_script_state{{{},state=\"failed\"}} 0\n", labels)));
+        assert!(metrics.contains(&format!("runner_script_restarts{{{}}} 2\n", labels)));
+        assert!(metrics.contains(&format!("runner_script_last_exit_code{{{}}} 3\n", labels)));
+        assert!(metrics.contains("# TYPE runner_script_start_attempts_total counter\n"));
+        assert!(metrics.contains(&format!("runner_script_cpu_seconds_total{{{}}} 1.5\n", labels)));
+        assert!(!metrics.contains("runner_script_rss_bytes{"));
+    }
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::io::Error;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use crate::utilities::build_invalid_data_error_str;
+
+pub struct ProcessStats {
+    cpu_time: Duration,
+    rss_bytes: u64,
+    sampled: Instant,
+}
+
+struct ProcessStat {
+    pgrp: u32,
+    cpu_ticks: u64,
+    rss_pages: u64,
+}
+
+/// Parses /proc/<pid>/stat contents, fields are counted after the command name
+/// because it can contain spaces.
+fn parse_stat(contents: &str) -> Option<ProcessStat> {
+    let fields: Vec<&str> = contents[contents.rfind(')')? + 1..].split_whitespace().collect();
+    let field = |idx: usize| fields.get(idx).and_then(|f| u64::from_str(f).ok());
+    Some(ProcessStat {
+        pgrp: field(2)? as u32,
+        cpu_ticks: field(11)? + field(12)?,
+        rss_pages: field(21)?,
+    })
+}
+
+impl ProcessStats {
+    /// Sums cpu time and resident memory of all the processes in the group,
+    /// works only on Linux because /proc is used.
+    pub fn read_group(pgid: u32) -> Result<ProcessStats, Error> {
+        let clock_ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+        let mut ticks = 0;
+        let mut pages = 0;
+        let mut found = false;
+        for entry in fs::read_dir("/proc")? {
+            let path = entry?.path();
+            if !path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.bytes().all(|b| b.is_ascii_digit())) {
+                continue;
+            }
+            // processes can finish while /proc is scanned
+            let stat = match fs::read_to_string(path.join("stat")).ok().and_then(|c| parse_stat(&c)) {
+                Some(s) => s,
+                None => continue
+            };
+            if stat.pgrp == pgid {
+                found = true;
+                ticks += stat.cpu_ticks;
+                pages += stat.rss_pages;
+            }
+        }
+        if !found {
+            return Err(build_invalid_data_error_str("process group not found"));
+        }
+        Ok(ProcessStats {
+            cpu_time: Duration::from_millis(ticks * 1000 / clock_ticks),
+            rss_bytes: pages * page_size,
+            sampled: Instant::now(),
+        })
+    }
+
+    pub fn get_cpu_time(&self) -> Duration {
+        self.cpu_time
+    }
+
+    pub fn get_rss_bytes(&self) -> u64 {
+        self.rss_bytes
+    }
+
+    /// Returns cpu usage in percents of one core since the previous sample.
+    pub fn cpu_percent_since(&self, previous: &ProcessStats) -> f64 {
+        let elapsed = self.sampled.duration_since(previous.sampled).as_secs_f64();
+        if elapsed <= 0.0 {
+            return 0.0;
+        }
+        self.cpu_time.saturating_sub(previous.cpu_time).as_secs_f64() * 100.0 / elapsed
+    }
+
+    /// Returns cpu usage in percents of one core over the given process run time.
+    pub fn cpu_percent_over(&self, run_time: Duration) -> f64 {
+        if run_time.is_zero() {
+            return 0.0;
+        }
+        self.cpu_time.as_secs_f64() * 100.0 / run_time.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::process_stats::parse_stat;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = parse_stat("1234 (some (odd) name) S 1 1230 1230 0 -1 4194560 100 0 0 0 \
+            250 50 0 0 20 0 1 0 12345 10000000 300 18446744073709551615").unwrap();
+        assert_eq!(stat.pgrp, 1230);
+        assert_eq!(stat.cpu_ticks, 300);
+        assert_eq!(stat.rss_pages, 300);
+        assert!(parse_stat("1234 (name) S 1").is_none());
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{BufRead, Error, ErrorKind, Read, Write};
+use serde_json::{json, Value};
+use crate::command_result::{error_code, OutputFormat};
+use crate::events::Event;
+
+/// Newline delimited json protocol: the client sends hello frame with the protocol version
+/// and optional token, the server answers with hello or error frame. After that the client sends
+/// request frames, the server answers every request with output frames and one final result frame.
+pub const PROTOCOL_VERSION: u64 = 1;
+const MAX_FRAME_SIZE: u64 = 1024 * 1024;
+
+pub fn read_frame(reader: &mut impl BufRead) -> Result<Option<Value>, Error> {
+    let mut line = String::new();
+    if reader.take(MAX_FRAME_SIZE).read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        return Err(Error::new(ErrorKind::InvalidData, "frame is too long or incomplete"));
+    }
+    serde_json::from_str(&line).map(Some)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("invalid frame: {}", e)))
+}
+
+pub fn write_frame(writer: &mut impl Write, frame: &Value) -> Result<(), Error> {
+    writeln!(writer, "{}", frame)?;
+    writer.flush()
+}
+
+pub fn get_frame_type(frame: &Value) -> &str {
+    frame["type"].as_str().unwrap_or("")
+}
+
+pub fn hello_frame(token: Option<&String>) -> Value {
+    match token {
+        Some(t) => json!({"type": "hello", "version": PROTOCOL_VERSION, "token": t}),
+        None => json!({"type": "hello", "version": PROTOCOL_VERSION})
+    }
+}
+
+pub fn parse_hello(frame: &Value) -> Result<Option<String>, Error> {
+    if get_frame_type(frame) != "hello" {
+        return Err(Error::new(ErrorKind::InvalidData, "hello frame expected"));
+    }
+    match frame["version"].as_u64() {
+        Some(PROTOCOL_VERSION) => Ok(frame["token"].as_str().map(|t| t.to_string())),
+        v => Err(Error::new(ErrorKind::Unsupported,
+                            format!("unsupported protocol version {:?}, supported version is {}",
+                                    v, PROTOCOL_VERSION)))
+    }
+}
+
+pub fn error_frame(error: &Error) -> Value {
+    json!({"type": "error", "error": {"code": error_code(error), "message": error.to_string()}})
+}
+
+pub fn request_frame(id: u64, command: &[String], format: OutputFormat) -> Value {
+    json!({"type": "request", "id": id, "command": command, "format": format.name()})
+}
+
+pub fn parse_request(frame: &Value) -> Result<(u64, Vec<String>, OutputFormat), Error> {
+    let invalid = |text: &str| Error::new(ErrorKind::InvalidInput, format!("invalid request: {}", text));
+    if get_frame_type(frame) != "request" {
+        return Err(invalid("request frame expected"));
+    }
+    let id = frame["id"].as_u64().ok_or(invalid("id is missing"))?;
+    let command = frame["command"].as_array()
+        .and_then(|c| c.iter().map(|p| p.as_str().map(|s| s.to_string())).collect::<Option<Vec<_>>>())
+        .ok_or(invalid("command must be an array of strings"))?;
+    let format = match frame["format"].as_str() {
+        Some(f) => OutputFormat::from_name(f)?,
+        None => OutputFormat::Text
+    };
+    Ok((id, command, format))
+}
+
+pub fn output_frame(id: u64, text: &str) -> Value {
+    json!({"type": "output", "id": id, "text": text})
+}
+
+/// Event frame carries both text and json forms, so the client does not format the event itself.
+pub fn event_frame(id: u64, event: &Event) -> Value {
+    json!({"type": "event", "id": id, "text": event.to_string(), "event": event.to_json()})
+}
+
+/// Sent while no events arrive, the write fails when the client is disconnected.
+pub fn heartbeat_frame(id: u64) -> Value {
+    json!({"type": "heartbeat", "id": id})
+}
+
+pub fn result_frame(id: u64, result: Value) -> Value {
+    json!({"type": "result", "id": id, "result": result})
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+    use serde_json::json;
+    use crate::command_result::OutputFormat;
+    use crate::protocol::{hello_frame, parse_hello, parse_request, read_frame, request_frame};
+
+    #[test]
+    fn test_frames() {
+        let command = vec!["start".to_string(), "with space".to_string()];
+        let data = format!("{}\n{}\n", hello_frame(Some(&"secret".to_string())),
+                           request_frame(7, &command, OutputFormat::Json));
+        let mut reader = BufReader::new(data.as_bytes());
+        let hello = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(parse_hello(&hello).unwrap(), Some("secret".to_string()));
+        let (id, parts, format) = parse_request(&read_frame(&mut reader).unwrap().unwrap()).unwrap();
+        assert!(id == 7 && parts == command && format == OutputFormat::Json);
+        assert!(read_frame(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_frames() {
+        assert!(parse_hello(&json!({"type": "hello", "version": 2})).is_err());
+        assert!(parse_request(&json!({"type": "request", "id": 1, "command": [1]})).is_err());
+        assert!(parse_request(&json!({"type": "request", "command": []})).is_err());
+        let mut reader = BufReader::new("{\"type\"".as_bytes());
+        assert!(read_frame(&mut reader).is_err());
+    }
+}
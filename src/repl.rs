@@ -0,0 +1,163 @@
+use std::env;
+use std::thread;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use crate::command_result::OutputFormat;
+use crate::service_manager::ServiceManager;
+use crate::user_command::{run_user_commands, WriterWithTCP, USER_COMMANDS};
+
+const PROMPT: &str = "runner> ";
+const HISTORY_FILE: &str = ".runner_history";
+
+/// Commands that need a client connection are not offered in the console.
+const CLIENT_ONLY_COMMANDS: [&str; 2] = ["events", "watch"];
+
+struct CompletionNames {
+    service_sets: Vec<String>,
+    services: Vec<String>,
+    scripts: Vec<String>,
+}
+
+impl CompletionNames {
+    fn new(manager: &ServiceManager) -> CompletionNames {
+        CompletionNames {
+            service_sets: manager.get_service_set_names(),
+            services: manager.get_service_names(),
+            scripts: manager.get_script_names(),
+        }
+    }
+}
+
+/// Returns the start of the completed word and the candidates for the text before the cursor.
+fn complete_line(line: &str, names: &CompletionNames) -> (usize, Vec<String>) {
+    let start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+    let word = &line[start..];
+    let previous = line[..start].split_whitespace().collect::<Vec<_>>();
+    let services_and_scripts = || names.services.iter().chain(names.scripts.iter()).cloned().collect::<Vec<_>>();
+    let candidates: Vec<String> = match previous.first() {
+        None => USER_COMMANDS.iter()
+            .filter(|c| !CLIENT_ONLY_COMMANDS.contains(c))
+            .map(|c| c.to_string())
+            .collect(),
+        Some(&"up") if previous.len() == 1 => names.service_sets.clone(),
+        Some(&"status") => ["--long", "--history"].iter().map(|o| o.to_string())
+            .filter(|_o| previous.len() == 1)
+            .chain(names.services.iter().cloned())
+            .collect(),
+        Some(&"wait_for_scripts") if previous.len() == 1 =>
+            std::iter::once("timeout".to_string()).chain(names.scripts.iter().cloned()).collect(),
+        Some(&"wait_for_scripts") if previous[previous.len() - 1] == "timeout" => Vec::new(),
+        Some(&"wait_for_scripts") => names.scripts.clone(),
+        Some(&"start") | Some(&"force-start") | Some(&"stop") | Some(&"restart") => services_and_scripts(),
+        _ => Vec::new()
+    };
+    let mut result = candidates.into_iter().filter(|c| c.starts_with(word)).collect::<Vec<_>>();
+    result.sort();
+    (start, result)
+}
+
+/// Completes command names, service sets, services and service.script names,
+/// the names are read from the manager on every completion, so they follow the reloads.
+struct ReplHelper {
+    manager: &'static ServiceManager,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, candidates) = complete_line(&line[..pos], &CompletionNames::new(self.manager));
+        let pairs = candidates.into_iter()
+            .map(|c| Pair { display: c.clone(), replacement: c + " " })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+fn get_history_path() -> Option<String> {
+    env::var("HOME").ok().map(|home| format!("{}/{}", home, HISTORY_FILE))
+}
+
+fn run_repl(manager: &'static ServiceManager, noexec: bool) -> rustyline::Result<()> {
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ReplHelper { manager }));
+    let history_path = get_history_path();
+    if let Some(path) = &history_path {
+        let _ = editor.load_history(path);
+    }
+    loop {
+        match editor.readline(PROMPT) {
+            Ok(line) => {
+                let parts = line.split_whitespace().map(|p| p.to_string()).collect::<Vec<_>>();
+                if parts.is_empty() {
+                    continue;
+                }
+                editor.add_history_entry(line.as_str())?;
+                if let Some(path) = &history_path {
+                    let _ = editor.save_history(path);
+                }
+                run_user_commands(parts, manager, noexec, WriterWithTCP::new(None, OutputFormat::Text, 0));
+            }
+            // Ctrl+C in the prompt shuts down the server like the interrupt signal does
+            Err(ReadlineError::Interrupted) => {
+                run_user_commands(vec!["exit".to_string()], manager, noexec,
+                                  WriterWithTCP::new(None, OutputFormat::Text, 0));
+            }
+            Err(ReadlineError::Eof) => {
+                println!("Console closed, the server keeps running");
+                return Ok(());
+            }
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+/// Starts the console on the server stdin when stdin is a terminal.
+pub fn start_repl(manager: &'static ServiceManager, noexec: bool) {
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+        return;
+    }
+    thread::spawn(move || {
+        if let Err(e) = run_repl(manager, noexec) {
+            println!("Console error: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repl::{complete_line, CompletionNames};
+
+    #[test]
+    fn test_complete_line() {
+        let names = CompletionNames {
+            service_sets: vec!["all".to_string()],
+            services: vec!["kafka".to_string(), "redis".to_string()],
+            scripts: vec!["kafka.server".to_string(), "kafka.zookeeper".to_string()],
+        };
+        assert_eq!(complete_line("st", &names), (0, vec!["start".to_string(), "status".to_string(), "stop".to_string()]));
+        assert_eq!(complete_line("up ", &names), (3, vec!["all".to_string()]));
+        assert_eq!(complete_line("up all ", &names), (7, vec![]));
+        assert_eq!(complete_line("stop redis ka", &names).1, vec!["kafka", "kafka.server", "kafka.zookeeper"]);
+        assert_eq!(complete_line("status -", &names).1, vec!["--history", "--long"]);
+        assert_eq!(complete_line("wait_for_scripts timeout ", &names).1, Vec::<String>::new());
+        assert_eq!(complete_line("wait_for_scripts timeout 5 kafka.s", &names).1, vec!["kafka.server"]);
+        assert_eq!(complete_line("ev", &names).1, Vec::<String>::new());
+        assert_eq!(complete_line("watch ka", &names).1, Vec::<String>::new());
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::Error;
+use std::time::Duration;
+use yaml_rust::Yaml;
+use crate::utilities::build_invalid_data_error_string;
+
+const DEFAULT_MAX_RETRIES: usize = 5;
+const DEFAULT_BACKOFF: u64 = 1;
+const DEFAULT_MAX_BACKOFF: u64 = 60;
+const DEFAULT_RESET_WINDOW: u64 = 60;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RestartMode {
+    Never,
+    OnFailure,
+    Always,
+}
+
+pub struct RestartPolicy {
+    mode: RestartMode,
+    max_retries: usize,
+    backoff: Duration,
+    max_backoff: Duration,
+    reset_window: Duration,
+}
+
+fn parse_mode(mode: &str, name: &String) -> Result<RestartMode, Error> {
+    match mode {
+        "never" => Ok(RestartMode::Never),
+        "on-failure" => Ok(RestartMode::OnFailure),
+        "always" => Ok(RestartMode::Always),
+        _ => Err(build_invalid_data_error_string(
+            format!("invalid restart policy {} in script {}", mode, name)))
+    }
+}
+
+fn parse_number(item: &Yaml, default: u64, key: &str, name: &String) -> Result<u64, Error> {
+    match item {
+        Yaml::BadValue => Ok(default),
+        Yaml::Integer(v) if *v >= 0 => Ok(*v as u64),
+        _ => Err(build_invalid_data_error_string(
+            format!("restart {} is invalid in script {}", key, name)))
+    }
+}
+
+impl RestartPolicy {
+    pub fn never() -> RestartPolicy {
+        RestartPolicy {
+            mode: RestartMode::Never,
+            max_retries: DEFAULT_MAX_RETRIES,
+            backoff: Duration::from_secs(DEFAULT_BACKOFF),
+            max_backoff: Duration::from_secs(DEFAULT_MAX_BACKOFF),
+            reset_window: Duration::from_secs(DEFAULT_RESET_WINDOW),
+        }
+    }
+
+    /// Accepts either a plain mode (`restart: on-failure`) or a hash with
+    /// policy, max_retries, backoff, max_backoff and reset_window keys.
+    pub fn new(item: &Yaml, name: &String) -> Result<RestartPolicy, Error> {
+        let mut policy = RestartPolicy::never();
+        match item {
+            Yaml::BadValue => {}
+            Yaml::String(mode) => policy.mode = parse_mode(mode, name)?,
+            Yaml::Hash(_) => {
+                let mode = item["policy"].as_str()
+                    .ok_or(build_invalid_data_error_string(
+                        format!("restart policy is missing in script {}", name)))?;
+                policy.mode = parse_mode(mode, name)?;
+                policy.max_retries = parse_number(&item["max_retries"], DEFAULT_MAX_RETRIES as u64,
+                                                   "max_retries", name)? as usize;
+                policy.backoff = Duration::from_secs(
+                    parse_number(&item["backoff"], DEFAULT_BACKOFF, "backoff", name)?);
+                policy.max_backoff = Duration::from_secs(
+                    parse_number(&item["max_backoff"], DEFAULT_MAX_BACKOFF, "max_backoff", name)?);
+                policy.reset_window = Duration::from_secs(
+                    parse_number(&item["reset_window"], DEFAULT_RESET_WINDOW, "reset_window", name)?);
+            }
+            _ => return Err(build_invalid_data_error_string(
+                format!("restart is invalid in script {}", name)))
+        }
+        Ok(policy)
+    }
+
+    /// Returns the delay before the next restart or None when the script should stay stopped.
+    /// attempt is the number of restarts already made since the last reset, forced restarts
+    /// (liveness action restart) ignore the mode but still follow the backoff and max_retries.
+    pub fn next_delay(&self, attempt: usize, success: bool, forced: bool) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        if !forced && (self.mode == RestartMode::Never || (self.mode == RestartMode::OnFailure && success)) {
+            return None;
+        }
+        let factor = 1u32.checked_shl(attempt as u32).unwrap_or(u32::MAX);
+        Some(self.backoff.saturating_mul(factor).min(self.max_backoff))
+    }
+
+    pub fn get_reset_window(&self) -> Duration {
+        self.reset_window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use yaml_rust::YamlLoader;
+    use crate::restart_policy::RestartPolicy;
+
+    fn build_policy(text: &str) -> RestartPolicy {
+        let docs = YamlLoader::load_from_str(text).unwrap();
+        RestartPolicy::new(&docs[0]["restart"], &"test".to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_next_delay() {
+        let policy = build_policy("restart:\n  policy: on-failure\n  max_retries: 4\n  backoff: 2\n  max_backoff: 10");
+        assert_eq!(policy.next_delay(0, true, false), None);
+        assert_eq!(policy.next_delay(0, false, false), Some(Duration::from_secs(2)));
+        assert_eq!(policy.next_delay(1, false, false), Some(Duration::from_secs(4)));
+        assert_eq!(policy.next_delay(2, false, false), Some(Duration::from_secs(8)));
+        assert_eq!(policy.next_delay(3, false, false), Some(Duration::from_secs(10)));
+        assert_eq!(policy.next_delay(4, false, false), None);
+
+        let policy = build_policy("restart: always");
+        assert_eq!(policy.next_delay(0, true, false), Some(Duration::from_secs(1)));
+
+        let policy = build_policy("command: test");
+        assert_eq!(policy.next_delay(0, false, false), None);
+        assert_eq!(policy.next_delay(0, false, true), Some(Duration::from_secs(1)));
+        assert_eq!(policy.next_delay(2, false, true), Some(Duration::from_secs(4)));
+        assert_eq!(policy.next_delay(5, false, true), None);
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        let docs = YamlLoader::load_from_str("restart: sometimes").unwrap();
+        assert!(RestartPolicy::new(&docs[0]["restart"], &"test".to_string()).is_err());
+        let docs = YamlLoader::load_from_str("restart:\n  max_retries: 1").unwrap();
+        assert!(RestartPolicy::new(&docs[0]["restart"], &"test".to_string()).is_err());
+    }
+}
@@ -1,43 +1,77 @@
 use std::collections::HashSet;
 use std::io::{Error, ErrorKind};
 use std::net::TcpStream;
+use std::process::{Child, ExitStatus};
 use std::str::FromStr;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::sleep;
-use std::time::Duration;
-use yaml_rust::Yaml;
+use std::time::{Duration, Instant, SystemTime};
+use serde_json::{json, Map, Value};
+use yaml_rust::{Yaml, YamlEmitter};
 use crate::command_to_run::CommandToRun;
+use crate::environment::Environment;
+use crate::events::{EventKind, publish};
+use crate::health_check::{HealthCheck, LivenessAction, LivenessCheck};
+use crate::process_stats::ProcessStats;
+use crate::restart_policy::RestartPolicy;
+use crate::script_state::{DEFAULT_HISTORY_SIZE, exit_status_json, format_exit_status, ScriptState, ScriptStateMachine};
+use crate::signals::{parse_signal, process_group_exists, send_group_signal};
 use crate::user_command::WriterWithTCP;
-use crate::utilities::build_invalid_data_error_string;
+use crate::utilities::{build_invalid_data_error_string, format_duration, format_time, read_last_lines};
 
-pub const SCRIPT_STATUS_NOT_STARTED: usize = 0;
-pub const SCRIPT_STATUS_STARTING: usize = 1;
-pub const SCRIPT_STATUS_RUNNING: usize = 2;
-pub const SCRIPT_STATUS_INTERRUPTED: usize = 3;
-pub const SCRIPT_STATUS_FINISHED: usize = 4;
-pub const SCRIPT_STATUS_FAILED: usize = 5;
-pub const SCRIPT_STATUS_KILLED: usize = 6;
+const DEFAULT_STOP_TIMEOUT: u64 = 10;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StatusFormat {
+    Short,
+    Long,
+    History,
+    /// long status with cumulative cpu seconds, it does not move the cpu percent sample window
+    Metrics,
+}
 
 pub trait ScriptChecker {
     fn script_exists(&self, script_name: &String) -> bool;
-    fn check_scripts(&self, scripts: &HashSet<String>) -> bool;
+    fn script_finished(&self, script_name: &str) -> bool;
+    fn script_ready(&self, script_name: &str) -> bool;
 }
 
 pub struct Script {
     name: String,
+    service_name: String,
     command: CommandToRun,
     wait_for_ports: HashSet<(String, u16)>,
     wait_until_scripts_are_done: HashSet<String>,
+    depends_on: Mutex<HashSet<String>>,
+    /// script yaml used to find the scripts changed by reload
+    definition: String,
     delay: Option<Duration>,
-    status: AtomicUsize,
+    start_timeout: Option<Duration>,
+    start_deadline: Mutex<Option<Instant>>,
+    restart_policy: RestartPolicy,
+    stop_signal: i32,
+    stop_timeout: Duration,
+    health_check: Option<HealthCheck>,
+    liveness_check: Option<LivenessCheck>,
+    failure_state: Mutex<Option<ScriptState>>,
+    liveness_restart: AtomicBool,
+    run_id: AtomicUsize,
+    restarts: AtomicUsize,
+    start_attempts: AtomicUsize,
+    health_check_failures: AtomicUsize,
+    state: Mutex<ScriptStateMachine>,
+    pid: Mutex<Option<u32>>,
+    started_at: Mutex<Option<SystemTime>>,
+    last_stats: Mutex<Option<ProcessStats>>,
+    last_error: Mutex<Option<String>>,
     tx: Mutex<Sender<()>>,
     rx: Mutex<Receiver<()>>,
 }
 
-fn process_host_port(host_port: &str, name: &String) -> Result<(String, u16), Error> {
+pub fn process_host_port(host_port: &str, name: &String) -> Result<(String, u16), Error> {
     let splitted: Vec<&str> = host_port.split(':').collect();
     let (host, port) = match splitted.len()  {
         1 => ("localhost", splitted[0]),
@@ -63,14 +97,15 @@ fn process_port(host: String, port: i64, name: &String) -> Result<(String, u16),
 }
 
 impl Script {
-    pub fn new(name: String, items: &Yaml, checker: &dyn ScriptChecker) -> Result<Script, Error> {
+    pub fn new(service_name: &str, name: String, items: &Yaml, environment: &Environment,
+               checker: &dyn ScriptChecker) -> Result<Script, Error> {
         let work_dir = items["workdir"].as_str().map(|s| s.to_string());
-        let env_file = items["env_file"].as_str().map(|s| s.to_string());
+        let env = environment.with_level(items, &format!("{}.{}", service_name, name))?;
         let log_file_out = items["log_file"].as_str().map(|s| s.to_string());
         let log_file_err = items["log_file_err"].as_str().map(|s| s.to_string());
         let command = match items["command"].as_str() {
             Some(c) => CommandToRun::new(c.to_string(), log_file_out,
-                                         log_file_err, work_dir, env_file)?,
+                                         log_file_err, work_dir, env)?,
             None => return Err(build_invalid_data_error_string(format!("script {} has no command", name)))
         };
         let mut wait_for_ports = HashSet::new();
@@ -97,119 +132,416 @@ impl Script {
                                   format!("wait_until_scripts_are_done is invalid in script {}", name)));
         }
         let delay = items["delay"].as_i64().map(|d|Duration::from_secs(d as u64));
+        let start_timeout = items["start_timeout"].as_i64().map(|t|Duration::from_secs(t as u64));
+        let restart_policy = RestartPolicy::new(&items["restart"], &name)?;
+        let stop_signal = parse_signal(&items["stop_signal"], &name)?;
+        let stop_timeout = Duration::from_secs(items["stop_timeout"].as_i64()
+            .map(|t|t as u64)
+            .unwrap_or(DEFAULT_STOP_TIMEOUT));
+        let health_check = HealthCheck::new(&items["healthcheck"], &name, &command)?;
+        let liveness_check = LivenessCheck::new(&items["liveness"], &name, &command)?;
+        let history_size = match &items["history_size"] {
+            Yaml::BadValue => DEFAULT_HISTORY_SIZE,
+            Yaml::Integer(s) if *s > 0 => *s as usize,
+            _ => return Err(build_invalid_data_error_string(format!("history_size is invalid in script {}", name)))
+        };
+        let mut definition = String::new();
+        YamlEmitter::new(&mut definition).dump(items)
+            .map_err(|e| build_invalid_data_error_string(format!("script {}: {:?}", name, e)))?;
         let (tx, rx): (Sender<()>, Receiver<()>) = channel();
         Ok(Script {
             name,
+            service_name: service_name.to_string(),
             command,
             wait_for_ports,
             wait_until_scripts_are_done,
+            depends_on: Mutex::new(HashSet::new()),
+            definition,
             delay,
-            status: AtomicUsize::new(SCRIPT_STATUS_NOT_STARTED),
+            start_timeout,
+            start_deadline: Mutex::new(None),
+            restart_policy,
+            stop_signal,
+            stop_timeout,
+            health_check,
+            liveness_check,
+            failure_state: Mutex::new(None),
+            liveness_restart: AtomicBool::new(false),
+            run_id: AtomicUsize::new(0),
+            restarts: AtomicUsize::new(0),
+            start_attempts: AtomicUsize::new(0),
+            health_check_failures: AtomicUsize::new(0),
+            state: Mutex::new(ScriptStateMachine::new(history_size)),
+            pid: Mutex::new(None),
+            started_at: Mutex::new(None),
+            last_stats: Mutex::new(None),
+            last_error: Mutex::new(None),
             tx: Mutex::new(tx),
             rx: Mutex::new(rx),
         })
     }
 
-    pub fn start(&'static self, forced_start: bool, checker: &'static (dyn ScriptChecker + Sync),
+    /// Script threads keep the script and the checker (the services it was started from) alive
+    /// until they finish, so reload can replace them.
+    pub fn start(self: &Arc<Self>, forced_start: bool, checker: Arc<dyn ScriptChecker + Send + Sync>,
                  noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
-        let status = self.status.load(Ordering::Relaxed);
-        if status == SCRIPT_STATUS_NOT_STARTED || status == SCRIPT_STATUS_INTERRUPTED ||
-            status == SCRIPT_STATUS_FINISHED || status == SCRIPT_STATUS_KILLED || status == SCRIPT_STATUS_FAILED {
-            self.status.store(SCRIPT_STATUS_STARTING, Ordering::Relaxed);
+        let can_start = {
+            let mut state = self.state.lock().unwrap();
+            state.get_state().can_start() && state.transition(ScriptState::Starting, None).is_ok()
+        };
+        if can_start {
+            publish(&self.get_full_name(), EventKind::State(ScriptState::Starting));
+            self.clear_interrupts();
+            self.reset_start_deadline();
+            *self.last_error.lock().unwrap() = None;
             writer.write_string(format!("Starting {}...", self.name));
+            writer.add_outcome(&self.get_full_name(), "starting", ScriptState::Starting.name());
+            let script = self.clone();
             if forced_start {
                 if noexec {
                     self.run_noexec();
                 } else {
-                    thread::spawn(|| {
-                        self.run_exec();
+                    thread::spawn(move || {
+                        script.run_exec();
                     });
                 }
             } else {
                 if noexec {
-                    thread::spawn(|| {
-                        if !self.wait_for_ports(&self.wait_for_ports) {
+                    thread::spawn(move || {
+                        if !script.wait_for_dependencies(checker.as_ref()) {
+                            return;
+                        }
+                        if !script.wait_for_ports(&script.wait_for_ports) {
                             return;
                         }
-                        if !self.wait_for_scripts(&self.wait_until_scripts_are_done, checker) {
+                        if !script.wait_for_scripts(&script.wait_until_scripts_are_done, checker.as_ref()) {
                             return;
                         }
-                        if let Some(d) = self.delay {
+                        if let Some(d) = script.delay {
                             sleep(d);
                         }
-                        self.run_noexec();
+                        script.run_noexec();
                     });
                 } else {
-                    thread::spawn(|| {
-                        if !self.wait_for_ports(&self.wait_for_ports) {
+                    thread::spawn(move || {
+                        if !script.wait_for_dependencies(checker.as_ref()) {
                             return;
                         }
-                        if !self.wait_for_scripts(&self.wait_until_scripts_are_done, checker) {
+                        if !script.wait_for_ports(&script.wait_for_ports) {
                             return;
                         }
-                        if let Some(d) = self.delay {
+                        if !script.wait_for_scripts(&script.wait_until_scripts_are_done, checker.as_ref()) {
+                            return;
+                        }
+                        if let Some(d) = script.delay {
                             sleep(d);
                         }
-                        self.run_exec();
+                        script.run_exec();
                     });
                 }
             }
+        } else {
+            writer.add_outcome(&self.get_full_name(), "skipped", self.get_state().name());
         }
         Ok(())
     }
 
-    fn run_exec(&self) {
+    pub fn get_full_name(&self) -> String {
+        format!("{}.{}", self.service_name, self.name)
+    }
+
+    pub fn read_logs(&self, count: usize) -> Result<Value, Error> {
+        let log_file_out = self.command.get_log_file_out();
+        let log_file_err = self.command.get_log_file_err();
+        if log_file_out.is_none() && log_file_err.is_none() {
+            return Err(Error::new(ErrorKind::NotFound, format!("script {} has no log files", self.get_full_name())));
+        }
+        let read = |log_file: &Option<String>| match log_file {
+            Some(f) => read_last_lines(f, count).map(|lines| json!({"file": f, "lines": lines})),
+            None => Ok(Value::Null)
+        };
+        Ok(json!({"script": self.get_full_name(), "stdout": read(log_file_out)?, "stderr": read(log_file_err)?}))
+    }
+
+    fn run_exec(self: &Arc<Self>) {
         self.run(false)
     }
 
-    fn run_noexec(&self) {
+    fn run_noexec(self: &Arc<Self>) {
         self.run(true)
     }
 
-    fn run(&self, noexec: bool) {
-        match self.command.run_async(noexec) {
+    fn run(self: &Arc<Self>, noexec: bool) {
+        let mut attempt = 0;
+        let mut restarted = false;
+        loop {
+            let started = Instant::now();
+            let success = match self.run_once(noexec, restarted) {
+                Some(s) => s,
+                None => return
+            };
+            if started.elapsed() >= self.restart_policy.get_reset_window() {
+                attempt = 0;
+            }
+            let forced = self.liveness_restart.swap(false, Ordering::Relaxed);
+            match self.restart_policy.next_delay(attempt, success, forced) {
+                Some(delay) => {
+                    attempt += 1;
+                    // starting status allows stop to interrupt the backoff
+                    self.set_state(ScriptState::Starting, None);
+                    println!("Restarting {} in {} seconds (attempt {})...", self.name, delay.as_secs(), attempt);
+                    if !self.wait_for(delay) {
+                        self.set_state(ScriptState::Interrupted, None);
+                        return;
+                    }
+                    self.add_restart();
+                    self.reset_start_deadline();
+                    restarted = true;
+                }
+                None => return
+            }
+        }
+    }
+
+    fn add_restart(&self) {
+        let restarts = self.restarts.fetch_add(1, Ordering::Relaxed) + 1;
+        publish(&self.get_full_name(), EventKind::Restarted(restarts));
+    }
+
+    /// Runs the command once and returns the child exit success flag or None when the script
+    /// was interrupted or was not executed.
+    fn run_once(self: &Arc<Self>, noexec: bool, restarted: bool) -> Option<bool> {
+        *self.failure_state.lock().unwrap() = None;
+        self.liveness_restart.store(false, Ordering::Relaxed);
+        self.start_attempts.fetch_add(1, Ordering::Relaxed);
+        match self.command.run_async(noexec, restarted) {
             Ok(Some(mut child)) => {
-                self.status.store(SCRIPT_STATUS_RUNNING, Ordering::Relaxed);
+                *self.pid.lock().unwrap() = Some(child.id());
+                *self.started_at.lock().unwrap() = Some(SystemTime::now());
+                *self.last_stats.lock().unwrap() = None;
+                self.set_state(ScriptState::Running, None);
                 println!("Started {}...", self.name);
+                let run_id = self.run_id.fetch_add(1, Ordering::Relaxed) + 1;
+                if self.health_check.is_some() || self.liveness_check.is_some() {
+                    let script = self.clone();
+                    thread::spawn(move || script.monitor(run_id));
+                }
+                let result = self.wait_child(&mut child);
+                *self.pid.lock().unwrap() = None;
+                result
+            }
+            Ok(None) => {
+                self.set_state(ScriptState::Finished, None);
+                println!("Finished {} with noexec", self.name);
+                None
+            }
+            Err(e) => {
+                self.set_state(ScriptState::Failed, None);
+                println!("Failed to start {}: {}", self.name, e);
+                *self.last_error.lock().unwrap() = Some(e.to_string());
+                Some(false)
+            }
+        }
+    }
+
+    fn wait_child(&self, child: &mut Child) -> Option<bool> {
+        let duration = Duration::from_millis(100);
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    self.set_state(ScriptState::Finished, Some(status));
+                    println!("Finished {} with exitcode {}", self.name, status);
+                    return Some(status.success());
+                }
+                Ok(None) => {
+                    if !self.wait(duration) {
+                        let (stopped_state, exit_status) = self.stop_child(child);
+                        let failure_state = self.failure_state.lock().unwrap().take();
+                        return match failure_state {
+                            Some(state) => {
+                                self.set_state(state, exit_status);
+                                Some(false)
+                            }
+                            None => {
+                                self.set_state(stopped_state, exit_status);
+                                None
+                            }
+                        };
+                    }
+                }
+                Err(e) => {
+                    let _ = send_group_signal(child.id(), libc::SIGKILL);
+                    let _ = child.wait();
+                    self.set_state(ScriptState::Failed, None);
+                    println!("Failed to wait {}: {}", self.name, e);
+                    *self.last_error.lock().unwrap() = Some(e.to_string());
+                    return Some(false);
+                }
+            }
+        }
+    }
+
+    fn monitor(&self, run_id: usize) {
+        if let Some(health_check) = &self.health_check {
+            if !self.wait_healthy(health_check, run_id) {
+                return;
+            }
+        }
+        if let Some(liveness_check) = &self.liveness_check {
+            self.check_liveness(liveness_check, run_id);
+        }
+    }
+
+    fn is_current_run(&self, run_id: usize) -> bool {
+        self.run_id.load(Ordering::Relaxed) == run_id
+    }
+
+    /// Probes the script until it becomes healthy, the script is stopped as failed
+    /// when all the retries are exhausted or as timed out when start_timeout expires.
+    fn wait_healthy(&self, health_check: &HealthCheck, run_id: usize) -> bool {
+        for _ in 0..health_check.get_retries() {
+            sleep(health_check.get_interval());
+            if self.get_state() != ScriptState::Running || !self.is_current_run(run_id) {
+                return false;
+            }
+            if health_check.check().is_ok() {
+                if self.compare_and_set_state(ScriptState::Running, ScriptState::Healthy) {
+                    println!("{} is healthy", self.name);
+                    return true;
+                }
+                return false;
+            }
+            self.health_check_failures.fetch_add(1, Ordering::Relaxed);
+            if self.start_deadline_passed() {
+                self.fail_running("waiting for health check".to_string(), ScriptState::TimedOut);
+                return false;
+            }
+        }
+        self.fail_running("health check failed".to_string(), ScriptState::Failed);
+        false
+    }
+
+    /// Probes the running script periodically, after failure_threshold consecutive failures
+    /// the script is marked as unhealthy and the liveness action is applied.
+    fn check_liveness(&self, liveness_check: &LivenessCheck, run_id: usize) {
+        let healthy_state = if self.health_check.is_some() { ScriptState::Healthy } else { ScriptState::Running };
+        let mut failures = 0;
+        loop {
+            sleep(liveness_check.get_interval());
+            let state = self.get_state();
+            if !self.is_current_run(run_id) || (state != healthy_state && state != ScriptState::Unhealthy) {
+                return;
+            }
+            if liveness_check.check().is_ok() {
+                failures = 0;
+                if self.compare_and_set_state(ScriptState::Unhealthy, healthy_state) {
+                    println!("{} is alive again", self.name);
+                    *self.last_error.lock().unwrap() = None;
+                }
+                continue;
+            }
+            self.health_check_failures.fetch_add(1, Ordering::Relaxed);
+            failures += 1;
+            if failures >= liveness_check.get_failure_threshold() &&
+                self.compare_and_set_state(healthy_state, ScriptState::Unhealthy) {
+                println!("{} is unhealthy after {} failed liveness checks", self.name, failures);
+                *self.last_error.lock().unwrap() = Some("liveness check failed".to_string());
+                let action = liveness_check.get_action();
+                if action.stops_script() {
+                    *self.failure_state.lock().unwrap() = action.get_failure_state();
+                    self.liveness_restart.store(action == LivenessAction::Restart, Ordering::Relaxed);
+                    let _ = self.interrupt();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Stops the running child, run loop sets the script state to failure_state.
+    fn fail_running(&self, error: String, failure_state: ScriptState) {
+        if self.get_state() == ScriptState::Running {
+            println!("Stopping {} ({})", self.name, error);
+            *self.last_error.lock().unwrap() = Some(error);
+            *self.failure_state.lock().unwrap() = Some(failure_state);
+            let _ = self.interrupt();
+        }
+    }
+
+    fn reset_start_deadline(&self) {
+        *self.start_deadline.lock().unwrap() = self.start_timeout.map(|t| Instant::now() + t);
+    }
+
+    fn start_deadline_passed(&self) -> bool {
+        self.start_deadline.lock().unwrap().is_some_and(|d| Instant::now() >= d)
+    }
+
+    fn set_timed_out(&self, waiting_for: String) {
+        println!("{} timed out waiting for {}", self.name, waiting_for);
+        *self.last_error.lock().unwrap() = Some(format!("waiting for {}", waiting_for));
+        self.set_state(ScriptState::TimedOut, None);
+    }
+
+    /// Sends stop_signal to the child process group and waits up to stop_timeout for all
+    /// the group processes to finish before killing the group. Returns the state the child
+    /// was stopped with and its exit status.
+    fn stop_child(&self, child: &mut Child) -> (ScriptState, Option<ExitStatus>) {
+        let pgid = child.id();
+        match send_group_signal(pgid, self.stop_signal) {
+            Ok(()) => {
+                let started = Instant::now();
                 let duration = Duration::from_millis(100);
-                loop {
-                    match child.try_wait() {
-                        Ok(Some(status)) => {
-                            self.status.store(SCRIPT_STATUS_FINISHED, Ordering::Relaxed);
-                            println!("Finished {} with exitcode {}", self.name, status);
-                            break;
-                        }
-                        Ok(None) => {
-                            if !self.wait(duration) {
-                                child.kill().unwrap();
+                let mut exit_status = None;
+                while started.elapsed() < self.stop_timeout {
+                    if exit_status.is_none() {
+                        match child.try_wait() {
+                            Ok(status) => exit_status = status,
+                            Err(e) => {
+                                println!("Failed to wait {}: {}", self.name, e);
                                 break;
                             }
                         }
-                        Err(e) => {
-                            child.kill().unwrap();
-                            self.status.store(SCRIPT_STATUS_FAILED, Ordering::Relaxed);
-                            println!("Failed to wait {}: {}", self.name, e);
-                            break;
+                    }
+                    if let Some(status) = exit_status {
+                        if !process_group_exists(pgid) {
+                            println!("Interrupted {} with exitcode {}", self.name, status);
+                            return (ScriptState::Interrupted, Some(status));
                         }
                     }
+                    sleep(duration);
                 }
             }
-            Ok(None) => {
-                self.status.store(SCRIPT_STATUS_FINISHED, Ordering::Relaxed);
-                println!("Finished {} with noexec", self.name);
-            }
-            Err(e) => {
-                self.status.store(SCRIPT_STATUS_FAILED, Ordering::Relaxed);
-                println!("Failed to start {}: {}", self.name, e);
+            // the whole group has already exited, the child only needs to be reaped
+            Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {
+                if let Ok(status) = child.wait() {
+                    println!("{} has already finished with exitcode {}", self.name, status);
+                    return (ScriptState::Interrupted, Some(status));
+                }
             }
+            Err(e) => println!("Failed to send stop signal to {}: {}", self.name, e)
+        }
+        println!("Killing {}...", self.name);
+        if let Err(e) = send_group_signal(pgid, libc::SIGKILL) {
+            println!("Failed to kill {}: {}", self.name, e);
         }
+        (ScriptState::Killed, child.wait().ok())
     }
 
     pub fn stop(&self, writer: &mut WriterWithTCP) -> Result<(), Error> {
         writer.write_string(format!("Stopping {}...", self.name));
-        match self.status.load(Ordering::Relaxed) {
-            SCRIPT_STATUS_STARTING | SCRIPT_STATUS_RUNNING => self.interrupt(),
-            _ => Ok(())
+        let state = self.get_state();
+        match state {
+            ScriptState::Starting | ScriptState::Running | ScriptState::Healthy |
+            ScriptState::Unhealthy => {
+                writer.add_outcome(&self.get_full_name(), "stopping", state.name());
+                // a user stop wins over a pending liveness restart
+                *self.failure_state.lock().unwrap() = None;
+                self.liveness_restart.store(false, Ordering::Relaxed);
+                self.interrupt()
+            }
+            _ => {
+                writer.add_outcome(&self.get_full_name(), "skipped", state.name());
+                Ok(())
+            }
         }
     }
 
@@ -217,14 +549,94 @@ impl Script {
         self.tx.lock().unwrap().send(()).map_err(|_e| Error::new(ErrorKind::Other, "send error"))
     }
 
-    pub fn get_status(&self) -> usize {
-        self.status.load(Ordering::Relaxed)
+    pub fn get_state(&self) -> ScriptState {
+        self.state.lock().unwrap().get_state()
+    }
+
+    /// Invalid transitions are reported and ignored.
+    fn set_state(&self, next: ScriptState, exit_status: Option<ExitStatus>) {
+        if let Err(e) = self.state.lock().unwrap().transition(next, exit_status) {
+            println!("{}: {}", self.name, e);
+            return;
+        }
+        if let Some(status) = exit_status {
+            publish(&self.get_full_name(), EventKind::Exited(status));
+        }
+        publish(&self.get_full_name(), EventKind::State(next));
+    }
+
+    fn compare_and_set_state(&self, expected: ScriptState, next: ScriptState) -> bool {
+        let changed = self.state.lock().unwrap().compare_and_transition(expected, next);
+        if changed {
+            publish(&self.get_full_name(), EventKind::State(next));
+        }
+        changed
+    }
+
+    pub fn set_dependencies(&self, depends_on: HashSet<String>) {
+        *self.depends_on.lock().unwrap() = depends_on;
+    }
+
+    /// Scripts with the same yaml, environment and dependencies do not need a restart on reload.
+    pub fn has_same_definition(&self, other: &Script) -> bool {
+        self.definition == other.definition && self.command.get_env() == other.command.get_env() &&
+            *self.depends_on.lock().unwrap() == *other.depends_on.lock().unwrap()
+    }
+
+    /// Script is ready for its dependents when it is healthy (running when it has no health check)
+    /// or has finished successfully.
+    pub fn is_ready(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        match state.get_state() {
+            ScriptState::Healthy => true,
+            ScriptState::Running => self.health_check.is_none(),
+            ScriptState::Finished => state.get_history().back()
+                .and_then(|t| t.exit_status)
+                .is_none_or(|s| s.success()),
+            _ => false
+        }
+    }
+
+    fn wait_for_dependencies(&self, checker: &dyn ScriptChecker) -> bool {
+        let duration = Duration::from_millis(500);
+        let depends_on = self.depends_on.lock().unwrap().clone();
+        while let Some(script) = depends_on.iter().find(|s| !checker.script_ready(s)) {
+            if self.start_deadline_passed() {
+                self.set_timed_out(format!("dependency {}", script));
+                return false;
+            }
+            if !self.wait(duration) {
+                self.set_state(ScriptState::Interrupted, None);
+                return false;
+            }
+        }
+        true
     }
 
     fn wait_for_scripts(&self, scripts: &HashSet<String>, checker: &dyn ScriptChecker) -> bool {
         let duration = Duration::from_secs(1);
-        while !checker.check_scripts(scripts) {
+        while let Some(script) = scripts.iter().find(|s| !checker.script_finished(s)) {
+            if self.start_deadline_passed() {
+                self.set_timed_out(format!("script {}", script));
+                return false;
+            }
             if !self.wait(duration) {
+                self.set_state(ScriptState::Interrupted, None);
+                return false;
+            }
+        }
+        true
+    }
+
+    fn clear_interrupts(&self) {
+        while self.rx.lock().unwrap().try_recv().is_ok() {}
+    }
+
+    fn wait_for(&self, duration: Duration) -> bool {
+        let step = Duration::from_millis(100);
+        let started = Instant::now();
+        while started.elapsed() < duration {
+            if !self.wait(step.min(duration.saturating_sub(started.elapsed()))) {
                 return false;
             }
         }
@@ -234,12 +646,12 @@ impl Script {
     fn wait(&self, duration: Duration) -> bool {
         match self.rx.lock().unwrap().try_recv() {
             Ok(_data) => {
-                self.status.store(SCRIPT_STATUS_INTERRUPTED, Ordering::Relaxed);
+                self.set_state(ScriptState::Stopping, None);
                 return false;
             }
             Err(TryRecvError::Empty) => sleep(duration),
             Err(TryRecvError::Disconnected) => {
-                self.status.store(SCRIPT_STATUS_INTERRUPTED, Ordering::Relaxed);
+                self.set_state(ScriptState::Stopping, None);
                 return false;
             }
         }
@@ -248,35 +660,133 @@ impl Script {
 
     fn wait_for_ports(&self, ports: &HashSet<(String, u16)>) -> bool {
         let duration = Duration::from_secs(1);
-        while !ports.iter().all(|(host, port)| TcpStream::connect((host.as_str(), *port)).is_ok()) {
+        let mut waiting_for = None;
+        while let Some((host, port)) = ports.iter()
+            .find(|(host, port)| TcpStream::connect((host.as_str(), *port)).is_err()) {
+            if waiting_for != Some((host, port)) {
+                publish(&self.get_full_name(), EventKind::WaitingForPort(host.clone(), *port));
+                waiting_for = Some((host, port));
+            }
+            if self.start_deadline_passed() {
+                self.set_timed_out(format!("port {}:{}", host, port));
+                return false;
+            }
             if !self.wait(duration) {
+                self.set_state(ScriptState::Interrupted, None);
                 return false;
             }
         }
         true
     }
 
-    pub fn get_status_string(&self) -> String {
-        let status_string = match self.status.load(Ordering::Relaxed) {
-            SCRIPT_STATUS_STARTING => "starting",
-            SCRIPT_STATUS_KILLED => "killed",
-            SCRIPT_STATUS_FAILED => "failed",
-            SCRIPT_STATUS_FINISHED => "finished",
-            SCRIPT_STATUS_NOT_STARTED => "not started",
-            SCRIPT_STATUS_INTERRUPTED => "interrupted",
-            SCRIPT_STATUS_RUNNING => "running",
-            _ => "unknown"
+    pub fn get_status_string(&self, format: StatusFormat) -> String {
+        match format {
+            StatusFormat::Short => self.get_short_status_string(),
+            StatusFormat::Long | StatusFormat::Metrics => self.get_long_status_string(),
+            StatusFormat::History => self.get_history_string(),
+        }
+    }
+
+    fn get_short_status_string(&self) -> String {
+        let mut result = format!("  {}: {}", self.name, self.get_state());
+        let restarts = self.restarts.load(Ordering::Relaxed);
+        if restarts > 0 {
+            result += format!(" (restarts: {})", restarts).as_str();
+        }
+        if let Some(error) = self.last_error.lock().unwrap().as_ref() {
+            result += format!(" - {}", error).as_str();
+        }
+        result
+    }
+
+    fn get_long_status_string(&self) -> String {
+        let mut result = self.get_short_status_string();
+        let started_at = *self.started_at.lock().unwrap();
+        let pid = *self.pid.lock().unwrap();
+        if let Some(started) = started_at {
+            result += format!("\n    started: {}", format_time(started)).as_str();
+        }
+        if let (Some(pid), Some(started)) = (pid, started_at) {
+            let uptime = SystemTime::now().duration_since(started).unwrap_or_default();
+            result += format!("\n    pid: {}, uptime: {}", pid, format_duration(uptime)).as_str();
+            match self.read_usage(pid, uptime) {
+                Ok((cpu, rss)) => result += format!("\n    cpu: {:.1}%, rss: {:.1} MB", cpu,
+                                                    rss as f64 / (1024.0 * 1024.0)).as_str(),
+                Err(e) => result += format!("\n    cpu, rss: not available ({})", e).as_str()
+            }
+        }
+        if let Some(exit_status) = self.state.lock().unwrap().get_last_exit_status() {
+            result += format!("\n    last exit: {}", format_exit_status(&exit_status)).as_str();
+        }
+        result += format!("\n    restarts: {}", self.restarts.load(Ordering::Relaxed)).as_str();
+        result
+    }
+
+    fn read_usage(&self, pid: u32, uptime: Duration) -> Result<(f64, u64), Error> {
+        let stats = ProcessStats::read_group(pid)?;
+        let mut last_stats = self.last_stats.lock().unwrap();
+        let cpu = match last_stats.as_ref() {
+            Some(previous) => stats.cpu_percent_since(previous),
+            None => stats.cpu_percent_over(uptime)
         };
-        format!("  {}: {}", self.name, status_string)
+        let rss = stats.get_rss_bytes();
+        *last_stats = Some(stats);
+        Ok((cpu, rss))
+    }
+
+    pub fn get_status_json(&self, format: StatusFormat) -> Value {
+        let mut result = Map::new();
+        result.insert("state".to_string(), json!(self.get_state().name()));
+        result.insert("restarts".to_string(), json!(self.restarts.load(Ordering::Relaxed)));
+        result.insert("error".to_string(), json!(*self.last_error.lock().unwrap()));
+        match format {
+            StatusFormat::Short => {}
+            StatusFormat::Long | StatusFormat::Metrics => {
+                let started_at = *self.started_at.lock().unwrap();
+                let pid = *self.pid.lock().unwrap();
+                result.insert("started".to_string(), json!(started_at.map(format_time)));
+                result.insert("pid".to_string(), json!(pid));
+                if let (Some(pid), Some(started)) = (pid, started_at) {
+                    let uptime = SystemTime::now().duration_since(started).unwrap_or_default();
+                    result.insert("uptime_seconds".to_string(), json!(uptime.as_secs()));
+                    if format == StatusFormat::Metrics {
+                        if let Ok(stats) = ProcessStats::read_group(pid) {
+                            result.insert("cpu_seconds".to_string(), json!(stats.get_cpu_time().as_secs_f64()));
+                            result.insert("rss_bytes".to_string(), json!(stats.get_rss_bytes()));
+                        }
+                    } else if let Ok((cpu, rss)) = self.read_usage(pid, uptime) {
+                        result.insert("cpu_percent".to_string(), json!((cpu * 10.0).round() / 10.0));
+                        result.insert("rss_bytes".to_string(), json!(rss));
+                    }
+                }
+                let last_exit = self.state.lock().unwrap().get_last_exit_status();
+                result.insert("last_exit".to_string(), last_exit.map_or(Value::Null, |s| exit_status_json(&s)));
+                result.insert("start_attempts".to_string(), json!(self.start_attempts.load(Ordering::Relaxed)));
+                result.insert("health_check_failures".to_string(),
+                              json!(self.health_check_failures.load(Ordering::Relaxed)));
+            }
+            StatusFormat::History => {
+                let history = self.state.lock().unwrap().get_history().iter()
+                    .map(|t| t.to_json())
+                    .collect::<Vec<_>>();
+                result.insert("history".to_string(), Value::Array(history));
+            }
+        }
+        Value::Object(result)
+    }
+
+    fn get_history_string(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut result = format!("  {}:", self.name);
+        for transition in state.get_history() {
+            result += format!("\n    {}", transition).as_str();
+        }
+        result
     }
 
     pub fn wait_finish(&self) {
         let delay = Duration::from_millis(100);
-        loop {
-            let status = self.get_status();
-            if status != SCRIPT_STATUS_RUNNING && status != SCRIPT_STATUS_STARTING {
-                break;
-            }
+        while self.get_state().is_active() {
             sleep(delay);
         }
     }
@@ -284,7 +794,33 @@ impl Script {
 
 #[cfg(test)]
 mod tests {
-    use crate::script::process_host_port;
+    use std::collections::HashSet;
+    use std::process::Command;
+    use yaml_rust::YamlLoader;
+    use crate::environment::Environment;
+    use crate::script::{process_host_port, Script, ScriptChecker};
+    use crate::script_state::ScriptState;
+
+    struct NoScriptsReady;
+
+    impl ScriptChecker for NoScriptsReady {
+        fn script_exists(&self, _script_name: &String) -> bool {
+            true
+        }
+
+        fn script_finished(&self, _script_name: &str) -> bool {
+            false
+        }
+
+        fn script_ready(&self, _script_name: &str) -> bool {
+            false
+        }
+    }
+
+    fn build_script(yaml: &str) -> Script {
+        let items = YamlLoader::load_from_str(yaml).unwrap().swap_remove(0);
+        Script::new("service", "script".to_string(), &items, &Environment::default(), &NoScriptsReady).unwrap()
+    }
 
     fn check_host_port(host: &str, port: u16, input: &str, name: &String) {
         let result = process_host_port(&input.to_string(), name);
@@ -304,4 +840,40 @@ mod tests {
         assert!(process_host_port(&"aaaa:123456", &name).is_err());
         assert!(process_host_port(&"aaaa:1234:", &name).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_start_deadline() {
+        let script = build_script("command: /bin/true");
+        script.reset_start_deadline();
+        assert!(!script.start_deadline_passed());
+        let script = build_script("command: /bin/true\nstart_timeout: 60");
+        script.reset_start_deadline();
+        assert!(!script.start_deadline_passed());
+        let script = build_script("command: /bin/true\nstart_timeout: 0");
+        script.reset_start_deadline();
+        assert!(script.start_deadline_passed());
+    }
+
+    #[test]
+    fn test_set_timed_out() {
+        let script = build_script("command: /bin/true\nstart_timeout: 0");
+        script.set_dependencies(HashSet::from(["db.db".to_string()]));
+        script.state.lock().unwrap().transition(ScriptState::Starting, None).unwrap();
+        script.reset_start_deadline();
+        assert!(!script.wait_for_dependencies(&NoScriptsReady));
+        assert_eq!(script.get_state(), ScriptState::TimedOut);
+        assert_eq!(script.last_error.lock().unwrap().as_deref(), Some("waiting for dependency db.db"));
+        assert!(script.get_state().can_start());
+    }
+
+    #[test]
+    fn test_stop_finished_child() {
+        let script = build_script("command: /bin/true");
+        // the child is not a group leader, so the group signal fails with ESRCH
+        let mut child = Command::new("/bin/true").spawn().unwrap();
+        let (state, status) = script.stop_child(&mut child);
+        assert_eq!(state, ScriptState::Interrupted);
+        assert!(status.unwrap().success());
+        assert!(ScriptState::Stopping.can_transition_to(state));
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::io::{Error, ErrorKind};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::SystemTime;
+use serde_json::{json, Value};
+use crate::utilities::format_time;
+
+pub const DEFAULT_HISTORY_SIZE: usize = 20;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScriptState {
+    NotStarted,
+    Starting,
+    Running,
+    Healthy,
+    Unhealthy,
+    Stopping,
+    Interrupted,
+    Finished,
+    Failed,
+    Killed,
+    TimedOut,
+}
+
+pub const ALL_STATES: [ScriptState; 11] = [
+    ScriptState::NotStarted, ScriptState::Starting, ScriptState::Running, ScriptState::Healthy,
+    ScriptState::Unhealthy, ScriptState::Stopping, ScriptState::Interrupted, ScriptState::Finished,
+    ScriptState::Failed, ScriptState::Killed, ScriptState::TimedOut,
+];
+
+pub struct StateTransition {
+    pub time: SystemTime,
+    pub from: ScriptState,
+    pub to: ScriptState,
+    pub exit_status: Option<ExitStatus>,
+}
+
+pub struct ScriptStateMachine {
+    state: ScriptState,
+    last_exit_status: Option<ExitStatus>,
+    history: VecDeque<StateTransition>,
+    history_size: usize,
+}
+
+impl ScriptState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScriptState::NotStarted => "not started",
+            ScriptState::Starting => "starting",
+            ScriptState::Running => "running",
+            ScriptState::Healthy => "healthy",
+            ScriptState::Unhealthy => "unhealthy",
+            ScriptState::Stopping => "stopping",
+            ScriptState::Interrupted => "interrupted",
+            ScriptState::Finished => "finished",
+            ScriptState::Failed => "failed",
+            ScriptState::Killed => "killed",
+            ScriptState::TimedOut => "timed out",
+        }
+    }
+
+    /// Script has a child process or waits to start it.
+    pub fn is_active(&self) -> bool {
+        matches!(self, ScriptState::Starting | ScriptState::Running | ScriptState::Healthy |
+            ScriptState::Unhealthy | ScriptState::Stopping)
+    }
+
+    pub fn can_start(&self) -> bool {
+        matches!(self, ScriptState::NotStarted | ScriptState::Interrupted | ScriptState::Finished |
+            ScriptState::Failed | ScriptState::Killed | ScriptState::TimedOut)
+    }
+
+    pub fn can_transition_to(&self, next: ScriptState) -> bool {
+        match self {
+            ScriptState::NotStarted | ScriptState::Interrupted | ScriptState::Finished | ScriptState::Failed |
+            ScriptState::Killed | ScriptState::TimedOut => next == ScriptState::Starting,
+            ScriptState::Starting => matches!(next, ScriptState::Running | ScriptState::Stopping |
+                ScriptState::Interrupted | ScriptState::Finished | ScriptState::Failed | ScriptState::TimedOut),
+            ScriptState::Running => matches!(next, ScriptState::Healthy | ScriptState::Unhealthy |
+                ScriptState::Stopping | ScriptState::Finished | ScriptState::Failed),
+            ScriptState::Healthy => matches!(next, ScriptState::Unhealthy | ScriptState::Stopping |
+                ScriptState::Finished | ScriptState::Failed),
+            ScriptState::Unhealthy => matches!(next, ScriptState::Running | ScriptState::Healthy |
+                ScriptState::Stopping | ScriptState::Finished | ScriptState::Failed),
+            ScriptState::Stopping => matches!(next, ScriptState::Interrupted | ScriptState::Killed |
+                ScriptState::Failed | ScriptState::TimedOut),
+        }
+    }
+}
+
+impl Display for ScriptState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+pub fn format_exit_status(exit_status: &ExitStatus) -> String {
+    match (exit_status.code(), exit_status.signal()) {
+        (Some(code), _) => format!("exit code {}", code),
+        (None, Some(signal)) => format!("signal {}", signal),
+        _ => "unknown exit status".to_string()
+    }
+}
+
+pub fn exit_status_json(exit_status: &ExitStatus) -> Value {
+    json!({"code": exit_status.code(), "signal": exit_status.signal()})
+}
+
+impl StateTransition {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "time": format_time(self.time),
+            "from": self.from.name(),
+            "to": self.to.name(),
+            "exit": self.exit_status.as_ref().map(exit_status_json),
+        })
+    }
+}
+
+impl Display for StateTransition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} -> {}", format_time(self.time), self.from, self.to)?;
+        if let Some(exit_status) = &self.exit_status {
+            write!(f, " ({})", format_exit_status(exit_status))?;
+        }
+        Ok(())
+    }
+}
+
+impl ScriptStateMachine {
+    pub fn new(history_size: usize) -> ScriptStateMachine {
+        ScriptStateMachine {
+            state: ScriptState::NotStarted,
+            last_exit_status: None,
+            history: VecDeque::new(),
+            history_size,
+        }
+    }
+
+    pub fn get_state(&self) -> ScriptState {
+        self.state
+    }
+
+    /// Exit status of the last finished run, kept after the script is started again.
+    pub fn get_last_exit_status(&self) -> Option<ExitStatus> {
+        self.last_exit_status
+    }
+
+    pub fn get_history(&self) -> &VecDeque<StateTransition> {
+        &self.history
+    }
+
+    pub fn transition(&mut self, next: ScriptState, exit_status: Option<ExitStatus>) -> Result<(), Error> {
+        if !self.state.can_transition_to(next) {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  format!("invalid state transition {} -> {}", self.state, next)));
+        }
+        if exit_status.is_some() {
+            self.last_exit_status = exit_status;
+        }
+        if self.history.len() >= self.history_size {
+            self.history.pop_front();
+        }
+        self.history.push_back(StateTransition { time: SystemTime::now(), from: self.state, to: next, exit_status });
+        self.state = next;
+        Ok(())
+    }
+
+    pub fn compare_and_transition(&mut self, expected: ScriptState, next: ScriptState) -> bool {
+        self.state == expected && self.transition(next, None).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::script_state::{ScriptState, ScriptStateMachine};
+
+    #[test]
+    fn test_transitions() {
+        let mut machine = ScriptStateMachine::new(3);
+        assert!(machine.transition(ScriptState::Running, None).is_err());
+        assert!(machine.transition(ScriptState::Starting, None).is_ok());
+        assert!(machine.transition(ScriptState::Running, None).is_ok());
+        assert!(!machine.compare_and_transition(ScriptState::Starting, ScriptState::Healthy));
+        assert!(machine.compare_and_transition(ScriptState::Running, ScriptState::Healthy));
+        assert!(machine.transition(ScriptState::Starting, None).is_err());
+        assert!(machine.transition(ScriptState::Stopping, None).is_ok());
+        assert!(machine.transition(ScriptState::Killed, None).is_ok());
+        assert_eq!(machine.get_state(), ScriptState::Killed);
+        let history = machine.get_history();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].from, ScriptState::Running);
+        assert_eq!(history[2].to, ScriptState::Killed);
+    }
+
+    #[test]
+    fn test_state_groups() {
+        assert!(ScriptState::Unhealthy.is_active());
+        assert!(!ScriptState::TimedOut.is_active());
+        assert!(ScriptState::TimedOut.can_start());
+        assert!(!ScriptState::Stopping.can_start());
+    }
+}
@@ -1,64 +1,133 @@
-use std::io::{Error, Read, Write};
-use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::io::{BufReader, Error, ErrorKind};
+use std::process::exit;
+use std::thread;
+use serde_json::Value;
+use crate::auth::check_token;
+use crate::command_result::{error_code, exit_code, OutputFormat, EXIT_FAILED};
+use crate::control_stream::{ControlAddress, ControlListener, ControlStream};
+use crate::protocol::{error_frame, get_frame_type, hello_frame, parse_hello, parse_request, read_frame,
+                      request_frame, write_frame};
 use crate::service_manager::ServiceManager;
 use crate::user_command::{run_user_command, WriterWithTCP};
 
-pub fn server_start(port: u16, manager: &'static ServiceManager, noexec: bool) -> Result<(), Error> {
-    let listener = TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], port)))?;
-    println!("Server listening on port {}", port);
-    for stream in listener.incoming() {
-        match stream {
-            Ok(s) => run_command(manager, noexec, WriterWithTCP::new(Some(s))),
-            Err(e) => println!("Connection error {}", e.to_string())
+/// Every connection is served in its own thread, commands are rejected without execution
+/// when token is set and the client sends another one.
+pub fn server_start(listener: ControlListener, address: &ControlAddress, manager: &'static ServiceManager,
+                    noexec: bool, token: Option<String>) -> Result<(), Error> {
+    println!("Server listening on {}", address);
+    loop {
+        match listener.accept() {
+            Ok(s) => {
+                let token = token.clone();
+                thread::spawn(move || {
+                    let peer = s.get_peer();
+                    if let Err(e) = run_connection(manager, noexec, s, &token) {
+                        println!("Connection {} error: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => println!("Connection error {}", e)
         }
     }
-    Ok(())
 }
 
-fn run_command(manager: &'static ServiceManager, noexec: bool, mut writer: WriterWithTCP) {
-    let mut buffer = [0; 10000];
-    match writer.read(&mut buffer) {
-        Ok(amt) => {
-            if amt == 0 {
-                return;
-            }
-            if let Ok(command) = String::from_utf8(Vec::from(&buffer[0..amt])) {
-                let parts = command.split(' ').map(|s|s.to_string()).collect();
-                if let Err(e) = run_user_command(parts, manager, noexec, &mut writer) {
-                    let _ = writer.write_string(format!("{}", e));
-                }
-            } else {
-                println!("invalid command");
+fn run_connection(manager: &'static ServiceManager, noexec: bool, mut stream: ControlStream,
+                  token: &Option<String>) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let hello = read_frame(&mut reader)?
+        .ok_or(Error::new(ErrorKind::UnexpectedEof, "connection closed before hello"))?;
+    if let Err(e) = parse_hello(&hello).and_then(|provided| check_token(token, &provided)) {
+        println!("Rejected connection from {}: {}", stream.get_peer(), e);
+        write_frame(&mut stream, &error_frame(&e))?;
+        return stream.shutdown();
+    }
+    write_frame(&mut stream, &hello_frame(None))?;
+    while let Some(frame) = read_frame(&mut reader)? {
+        match parse_request(&frame) {
+            Ok((id, parts, format)) => {
+                let mut writer = WriterWithTCP::new(Some(stream.try_clone()?), format, id);
+                let result = run_user_command(&parts, manager, noexec, &mut writer);
+                writer.write_result(&parts, &result);
             }
+            Err(e) => write_frame(&mut stream, &error_frame(&e))?
         }
-        Err(e) => println!("Stream read error {}", e.to_string())
     }
     println!("Stream shutdown");
-    writer.shutdown();
+    stream.shutdown()
 }
 
-pub fn send_command_to_server(port: u16, command: String) -> Result<(), Error> {
-    let mut buffer = [0; 10000];
-    println!("Sending command {} to server...", command);
-    let mut stream = TcpStream::connect(
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port))?;
-    stream.write_all(command.as_bytes())?;
-    loop {
-        match stream.read(&mut buffer) {
-            Ok(length) => {
-                if length > 0 {
-                    match String::from_utf8(Vec::from(&buffer[0..length])) {
-                        Ok(s) => { print!("{}", s); continue; },
-                        Err(_e) => println!("incorrect response from the server")
-                    }
+fn print_error(format: OutputFormat, error: &Value) {
+    match format {
+        OutputFormat::Text => println!("{}", error["message"].as_str().unwrap_or("unknown error")),
+        OutputFormat::Json => println!("{}", serde_json::json!({"success": false, "error": error})),
+    }
+}
+
+fn get_error_code(error: &Value) -> String {
+    error["code"].as_str().unwrap_or("failed").to_string()
+}
+
+pub fn send_command_to_server(address: &ControlAddress, command: Vec<String>, format: Option<OutputFormat>,
+                              token: Option<String>) -> Result<Option<String>, Error> {
+    let format = format.unwrap_or(OutputFormat::Text);
+    // json output is kept parseable
+    if format == OutputFormat::Text {
+        println!("Sending command {} to server...", command.join(" "));
+    }
+    let mut stream = address.connect()?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    write_frame(&mut stream, &hello_frame(token.as_ref()))?;
+    let hello = read_frame(&mut reader)?
+        .ok_or(Error::new(ErrorKind::UnexpectedEof, "server closed the connection"))?;
+    if get_frame_type(&hello) == "error" {
+        print_error(format, &hello["error"]);
+        return Ok(Some(get_error_code(&hello["error"])));
+    }
+    parse_hello(&hello)?;
+    let id = 1;
+    write_frame(&mut stream, &request_frame(id, &command, format))?;
+    while let Some(frame) = read_frame(&mut reader)? {
+        if frame["id"].as_u64().is_some_and(|i| i != id) {
+            continue;
+        }
+        match get_frame_type(&frame) {
+            "output" if format == OutputFormat::Text => println!("{}", frame["text"].as_str().unwrap_or("")),
+            "event" => match format {
+                OutputFormat::Text => println!("{}", frame["text"].as_str().unwrap_or("")),
+                OutputFormat::Json => println!("{}", frame["event"]),
+            },
+            "result" => {
+                let result = &frame["result"];
+                let success = result["success"].as_bool().unwrap_or(false);
+                match format {
+                    OutputFormat::Text => if !success {
+                        print_error(format, &result["error"]);
+                    },
+                    OutputFormat::Json => println!("{}", result),
                 }
-                break;
+                let _ = stream.shutdown();
+                return Ok(if success { None } else { Some(get_error_code(&result["error"])) });
             }
-            Err(e) => {
-                stream.shutdown(Shutdown::Both)?;
-                return Err(e);
+            "error" => {
+                print_error(format, &frame["error"]);
+                return Ok(Some(get_error_code(&frame["error"])));
             }
+            _ => {}
         }
     }
-    Ok(())
-}
\ No newline at end of file
+    Err(Error::new(ErrorKind::UnexpectedEof, "server closed the connection before the result"))
+}
+
+/// Exits with the code mapped from the command error code, connection errors exit with failed code.
+pub fn run_client(address: &ControlAddress, command: Vec<String>, format: Option<OutputFormat>,
+                  token: Option<String>) -> Result<(), Error> {
+    match send_command_to_server(address, command, format, token) {
+        Ok(None) => Ok(()),
+        Ok(Some(code)) => exit(exit_code(&code)),
+        Err(e) => {
+            let error = serde_json::json!({"code": error_code(&e), "message": e.to_string()});
+            print_error(format.unwrap_or(OutputFormat::Text), &error);
+            exit(EXIT_FAILED)
+        }
+    }
+}
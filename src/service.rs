@@ -1,93 +1,128 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{Error, ErrorKind};
-use yaml_rust::Yaml;
+use std::sync::Arc;
+use serde_json::{Map, Value};
+use yaml_rust::{Yaml, YamlEmitter};
 use crate::command_to_run::CommandToRun;
-use crate::script::{Script, SCRIPT_STATUS_NOT_STARTED, ScriptChecker};
+use crate::environment::Environment;
+use crate::events::{EventKind, publish};
+use crate::script::{Script, ScriptChecker, StatusFormat};
+use crate::script_state::ScriptState;
 use crate::user_command::WriterWithTCP;
 use crate::utilities::build_invalid_data_error_string;
 
 pub struct Service {
     name: String,
     post_stop_script: Option<CommandToRun>,
-    scripts: HashMap<String, Script>,
+    /// service settings without the scripts, compared on reload
+    definition: String,
+    environment: Environment,
+    /// the same script is shared by the configurations before and after reload
+    scripts: HashMap<String, Arc<Script>>,
 }
 
 impl Service {
-    pub fn new(service_name: String, service: &Yaml, checker: &dyn ScriptChecker)
+    pub fn new(service_name: String, service: &Yaml, environment: &Environment, checker: &dyn ScriptChecker)
                -> Result<Service, Error> {
+        let environment = environment.with_level(service, &service_name)?;
         if let Some(scripts) = service["scripts"].as_hash() {
             if scripts.is_empty() {
                 return Err(build_service_has_no_scripts_error(&service_name));
             }
             let post_stop_script = match service["post-stop-script"].as_str() {
                 Some(s) => Some(CommandToRun::new(s.to_string(), None,
-                                                  None, None, None)?),
+                                                  None, None, environment.clone())?),
                 None => None
             };
+            let mut items = service.clone();
+            if let Yaml::Hash(hash) = &mut items {
+                hash.remove(&Yaml::String("scripts".to_string()));
+            }
+            let mut definition = String::new();
+            YamlEmitter::new(&mut definition).dump(&items)
+                .map_err(|e| build_invalid_data_error_string(format!("service {}: {:?}", service_name, e)))?;
             let mut result = HashMap::new();
             for (name, script_yaml) in scripts {
                 let script_name = name.as_str().unwrap().to_string();
                 println!(" - {}", script_name);
-                let script = Script::new(script_name.clone(), script_yaml, checker)?;
-                result.insert(script_name, script);
+                let script = Script::new(&service_name, script_name.clone(), script_yaml, &environment, checker)?;
+                result.insert(script_name, Arc::new(script));
             }
-            return Ok(Service { name: service_name, post_stop_script, scripts: result });
+            return Ok(Service { name: service_name, post_stop_script, definition, environment, scripts: result });
         }
         Err(build_service_has_no_scripts_error(&service_name))
     }
 
-    pub fn start(&'static self, forced_start: bool, checker: &'static (dyn ScriptChecker + Sync),
-                 noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
-        for (_name, script) in &self.scripts {
-            script.start(forced_start, checker, noexec, writer)?;
-        }
-        Ok(())
-    }
-
-    pub fn start_script(&'static self, script_name: &String, forced_start: bool, checker: &'static (dyn ScriptChecker + Sync),
+    pub fn start_script(&self, script_name: &String, forced_start: bool, checker: Arc<dyn ScriptChecker + Send + Sync>,
                         noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
         if let Some(script) = self.scripts.get(script_name) {
             return script.start(forced_start, checker, noexec, writer);
         }
-        Err(build_invalid_script_name_error())
+        Err(build_script_not_found_error())
     }
 
     pub fn stop_script(&self, script_name: &String, writer: &mut WriterWithTCP) -> Result<(), Error> {
         if let Some(script) = self.scripts.get(script_name) {
             return script.stop(writer);
         }
-        Err(build_invalid_script_name_error())
+        Err(build_script_not_found_error())
     }
 
-    pub fn stop(&self, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
-        for (_name, script) in &self.scripts {
-            script.stop(writer)?;
-        }
+    pub fn run_post_stop_script(&self, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
         if let Some(script) = &self.post_stop_script {
-            writer.write_string("Waiting for scripts to be interrupted...".to_string());
-            self.wait_finish();
             writer.write_string(format!("Running post-stop-script for {}", self.name));
             script.run_sync(noexec)?;
             writer.write_string(format!("Finished post-stop-script for {}", self.name));
+            publish(&self.name, EventKind::PostStopScript);
         }
         Ok(())
     }
 
+    pub fn get_script(&self, script_name: &String) -> Option<Arc<Script>> {
+        self.scripts.get(script_name).cloned()
+    }
+
+    pub fn reuse_script(&mut self, script_name: &str, script: Arc<Script>) {
+        self.scripts.insert(script_name.to_string(), script);
+    }
+
+    /// Compares the service settings, the scripts are compared separately.
+    pub fn has_same_definition(&self, other: &Service) -> bool {
+        self.definition == other.definition && self.environment == other.environment
+    }
+
+    pub fn get_script_names(&self) -> Vec<String> {
+        self.scripts.keys().cloned().collect()
+    }
+
+    pub fn set_script_dependencies(&self, script_name: &String, depends_on: HashSet<String>) {
+        if let Some(script) = self.scripts.get(script_name) {
+            script.set_dependencies(depends_on);
+        }
+    }
+
     pub fn script_exists(&self, script_name: String) -> bool {
         self.scripts.contains_key(script_name.as_str())
     }
 
-    pub fn get_script_status(&self, script_name: &String) -> usize {
-        self.scripts.get(script_name).map_or_else(||SCRIPT_STATUS_NOT_STARTED, |s|s.get_status())
+    pub fn get_script_state(&self, script_name: &String) -> ScriptState {
+        self.scripts.get(script_name).map_or_else(||ScriptState::NotStarted, |s|s.get_state())
     }
 
-    pub fn get_status_string(&self) -> String {
+    pub fn get_status_string(&self, format: StatusFormat) -> String {
         self.scripts.iter()
-            .map(|(_name, script)|script.get_status_string())
+            .map(|(_name, script)|script.get_status_string(format))
             .collect::<Vec<_>>()
             .join("\n")
     }
 
+    pub fn get_status_json(&self, format: StatusFormat) -> Value {
+        let scripts = self.scripts.iter()
+            .map(|(name, script)| (name.clone(), script.get_status_json(format)))
+            .collect::<Map<_, _>>();
+        Value::Object(scripts)
+    }
+
     pub fn wait_finish(&self) {
         self.scripts.iter()
             .for_each(|(_name, script)|script.wait_finish())
@@ -98,6 +133,10 @@ pub fn build_invalid_script_name_error() -> Error {
     Error::new(ErrorKind::InvalidInput, "invalid script name")
 }
 
+pub fn build_script_not_found_error() -> Error {
+    Error::new(ErrorKind::NotFound, "script not found")
+}
+
 fn build_service_has_no_scripts_error(service_name: &String) -> Error {
     build_invalid_data_error_string(format!("service {} has no scripts", service_name))
 }
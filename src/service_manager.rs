@@ -1,21 +1,51 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use serde_json::{json, Map, Value};
+use yaml_rust::Yaml;
 use yaml_rust::yaml::Hash;
 use crate::command_to_run::CommandToRun;
-use crate::script::{SCRIPT_STATUS_FINISHED, SCRIPT_STATUS_NOT_STARTED, ScriptChecker};
-use crate::service::{build_invalid_script_name_error, Service};
+use crate::config::load_config;
+use crate::dependency_graph::DependencyGraph;
+use crate::environment::Environment;
+use crate::script::{Script, ScriptChecker, StatusFormat};
+use crate::script_state::ScriptState;
+use crate::service::{build_invalid_script_name_error, build_script_not_found_error, Service};
 use crate::user_command::WriterWithTCP;
 use crate::utilities::{build_invalid_data_error_str, build_invalid_data_error_string};
 
 pub struct Services {
     services: HashMap<String, Service>,
+    service_sets: HashMap<String, HashSet<String>>,
+    dependencies: DependencyGraph,
+    start_order: Vec<String>,
 }
 
+type ServiceLocks = Vec<MutexGuard<'static, ()>>;
+
+#[derive(Default)]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+/// Changes found by reload, scripts are in the start order, services and service sets in the name order.
+pub struct ConfigDiff {
+    pub scripts: Changes,
+    pub services: Changes,
+    pub service_sets: Changes,
+}
+
+/// Services are replaced on reload, the previous services are freed
+/// when the last command or script thread using them finishes.
 pub struct ServiceManager {
-    service_sets: HashMap<String, HashSet<String>>,
-    services: Services,
+    config_file: Option<String>,
+    services: RwLock<Arc<Services>>,
+    service_locks: Mutex<BTreeMap<String, &'static Mutex<()>>>,
     init_command: Option<CommandToRun>,
     shutdown_command: Option<CommandToRun>,
 }
@@ -28,74 +58,278 @@ impl ScriptChecker for Services {
         false
     }
 
-    fn check_scripts(&self, scripts: &HashSet<String>) -> bool {
-        scripts.iter()
-            .map(|s| self.get_script_status(s))
-            .all(|s| s == SCRIPT_STATUS_FINISHED)
+    fn script_finished(&self, script_name: &str) -> bool {
+        self.get_script_state(script_name) == ScriptState::Finished
+    }
+
+    fn script_ready(&self, script_name: &str) -> bool {
+        self.get_script_service(script_name).ok()
+            .and_then(|(service, name)| service.get_script(&name).map(|s| s.is_ready()))
+            .unwrap_or(false)
     }
 }
 
 impl Services {
-    fn new(services: &Hash) -> Result<Services, Error> {
-        let mut result = Services{ services: HashMap::new() };
+    fn new(config: &Yaml) -> Result<Services, Error> {
+        let services = config["services"].as_hash()
+            .ok_or(build_invalid_data_error_str("could not find any service"))?;
+        let service_sets = config["service-sets"].as_hash()
+            .ok_or(build_invalid_data_error_str("could not find any service set"))?;
+        let mut result = Services{ services: HashMap::new(), service_sets: HashMap::new(),
+            dependencies: DependencyGraph::new(), start_order: Vec::new() };
+        let environment = Environment::default().with_level(config, "config")?;
+        let mut disabled_services = HashSet::new();
         for (name, service) in services {
             let disabled = service["disabled"].as_bool().unwrap_or(false);
+            let service_name = name.as_str().unwrap().to_string();
             if !disabled {
-                let service_name = name.as_str().unwrap().to_string();
                 println!("{}", service_name);
-                let service = Service::new(service_name.clone(), service, &result)?;
+                let service = Service::new(service_name.clone(), service, &environment, &result)?;
                 result.services.insert(service_name, service);
+            } else {
+                disabled_services.insert(service_name);
             }
         }
+        result.build_dependencies(services, &disabled_services)?;
+        result.service_sets = build_service_sets(service_sets, &result)?;
         Ok(result)
     }
 
-    fn get_script_status(&self, script_name: &String) -> usize {
+    fn get_script(&self, script_name: &str) -> Option<Arc<Script>> {
+        self.get_script_service(script_name).ok()
+            .and_then(|(service, name)| service.get_script(&name))
+    }
+
+    /// Takes unchanged scripts from the previous configuration, so they keep running,
+    /// and returns the changes compared to it. Service is changed when its settings (post-stop-script, env...)
+    /// are changed or any of its scripts is added, removed or changed.
+    fn reuse_unchanged_scripts(&mut self, previous: &Services) -> ConfigDiff {
+        let mut scripts = Changes::default();
+        for full_name in &previous.start_order {
+            match (previous.get_script(full_name), self.get_script(full_name)) {
+                (Some(old), Some(new)) if new.has_same_definition(&old) => {
+                    let (service_name, script_name) = full_name.split_once('.').unwrap();
+                    self.services.get_mut(service_name).unwrap().reuse_script(script_name, old);
+                }
+                (Some(_), Some(_)) => scripts.changed.push(full_name.clone()),
+                _ => scripts.removed.push(full_name.clone())
+            }
+        }
+        scripts.added = self.start_order.iter()
+            .filter(|name| !previous.start_order.contains(name))
+            .cloned()
+            .collect();
+        let changed_services = scripts.added.iter().chain(&scripts.removed).chain(&scripts.changed)
+            .map(|name| name.split('.').next().unwrap())
+            .collect::<HashSet<_>>();
+        let services = Changes::new(previous.services.keys(), self.services.keys(),
+                                    |name| changed_services.contains(name.as_str()) ||
+                                        !self.services[name].has_same_definition(&previous.services[name]));
+        let service_sets = Changes::new(previous.service_sets.keys(), self.service_sets.keys(),
+                                        |name| previous.service_sets[name] != self.service_sets[name]);
+        ConfigDiff { scripts, services, service_sets }
+    }
+
+    fn with_active_dependents(&self, scripts: &HashSet<String>) -> Vec<String> {
+        let dependents = self.dependencies.with_all_dependents(scripts);
+        self.start_order.iter()
+            .filter(|name| scripts.contains(*name) ||
+                (dependents.contains(*name) && self.get_script_state(name).is_active()))
+            .cloned()
+            .collect()
+    }
+
+    fn resolve_script_names(&self, names: &[String]) -> Result<HashSet<String>, Error> {
+        let mut result = HashSet::new();
+        for name in names {
+            if name.contains('.') {
+                self.get_script(name).ok_or(build_script_not_found_error())?;
+                result.insert(name.clone());
+            } else {
+                self.find_service(name)?;
+                result.extend(self.get_service_scripts(name).into_iter().cloned());
+            }
+        }
+        Ok(result)
+    }
+
+    /// Builds the dependency graph from service and script level depends_on lists.
+    /// Service level dependencies are added to every script of the service,
+    /// dependencies on disabled services are ignored.
+    fn build_dependencies(&mut self, services: &Hash, disabled_services: &HashSet<String>) -> Result<(), Error> {
+        let mut graph = DependencyGraph::new();
+        for (name, service_yaml) in services {
+            let service_name = name.as_str().unwrap().to_string();
+            if let Some(service) = self.services.get(&service_name) {
+                let service_dependencies = self.parse_dependencies(&service_yaml["depends_on"],
+                                                                   &service_name, disabled_services)?;
+                for script_name in service.get_script_names() {
+                    let full_name = format!("{}.{}", service_name, script_name);
+                    graph.add_node(full_name.clone());
+                    let script_dependencies = self.parse_dependencies(
+                        &service_yaml["scripts"][script_name.as_str()]["depends_on"], &full_name, disabled_services)?;
+                    for dependency in service_dependencies.iter().chain(script_dependencies.iter()) {
+                        graph.add_dependency(&full_name, dependency.clone());
+                    }
+                }
+            }
+        }
+        self.start_order = graph.topological_order()?;
+        for full_name in &self.start_order {
+            let (service_name, script_name) = full_name.split_once('.').unwrap();
+            self.services.get_mut(service_name).unwrap()
+                .set_script_dependencies(&script_name.to_string(), graph.get_dependencies(full_name));
+        }
+        self.dependencies = graph;
+        Ok(())
+    }
+
+    fn parse_dependencies(&self, item: &Yaml, name: &String, disabled_services: &HashSet<String>)
+        -> Result<HashSet<String>, Error> {
+        let mut result = HashSet::new();
+        if item.is_badvalue() {
+            return Ok(result);
+        }
+        let dependencies = item.as_vec()
+            .ok_or(build_invalid_data_error_string(format!("depends_on is invalid in {}", name)))?;
+        for dependency_yaml in dependencies {
+            let dependency = dependency_yaml.as_str()
+                .ok_or(build_invalid_data_error_string(format!("depends_on is invalid in {}", name)))?
+                .to_string();
+            let service_name = dependency.split('.').next().unwrap();
+            if disabled_services.contains(service_name) {
+                println!("{} depends on disabled service {}, ignoring", name, service_name);
+            } else if dependency.contains('.') {
+                if !self.script_exists(&dependency) {
+                    return Err(build_invalid_data_error_string(
+                        format!("{} depends on unknown script {}", name, dependency)));
+                }
+                result.insert(dependency);
+            } else {
+                let service = self.services.get(&dependency)
+                    .ok_or(build_invalid_data_error_string(
+                        format!("{} depends on unknown service {}", name, dependency)))?;
+                for script_name in service.get_script_names() {
+                    result.insert(format!("{}.{}", dependency, script_name));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn get_service_scripts(&self, service_name: &str) -> Vec<&String> {
+        self.start_order.iter()
+            .filter(|name| name.split('.').next() == Some(service_name))
+            .collect()
+    }
+
+    fn get_script_state(&self, script_name: &str) -> ScriptState {
         if let Ok((service, script_real_name)) = self.get_script_service(script_name) {
-            return service.get_script_status(&script_real_name);
+            return service.get_script_state(&script_real_name);
         }
-        SCRIPT_STATUS_NOT_STARTED
+        ScriptState::NotStarted
     }
 
     fn find_service(&self, service_name: &String) -> Result<&Service, Error> {
         self.services.get(service_name)
-            .map_or_else(||Err(Error::new(ErrorKind::InvalidInput, "service not found")),|s|Ok(s))
+            .map_or_else(||Err(Error::new(ErrorKind::NotFound, "service not found")),|s|Ok(s))
     }
 
-    pub fn start_service(&'static self, forced_start: bool, service_name: &String, noexec: bool,
-                         writer: &mut WriterWithTCP) -> Result<(), Error> {
-        let service = self.find_service(service_name)?;
-        service.start(forced_start, self, noexec, writer)
+    /// Starts the scripts after their dependencies which are not ready yet in the topological order,
+    /// forced_start applies to the given scripts only.
+    fn start_with_dependencies(self: &Arc<Self>, forced_start: bool, scripts: &HashSet<String>, noexec: bool,
+                               writer: &mut WriterWithTCP) -> Result<(), Error> {
+        let all = self.dependencies.with_all_dependencies(scripts);
+        for script_name in &self.start_order {
+            if scripts.contains(script_name) {
+                self.start_script(forced_start, script_name, noexec, writer)?;
+            } else if all.contains(script_name) && !self.script_ready(script_name) {
+                self.start_script(false, script_name, noexec, writer)?;
+            }
+        }
+        Ok(())
     }
 
-    pub fn start_script(&'static self, forced_start: bool, script_name: &String, noexec: bool,
+    pub fn start_script(self: &Arc<Self>, forced_start: bool, script_name: &str, noexec: bool,
                         writer: &mut WriterWithTCP) -> Result<(), Error> {
         let (service, script_name) = self.get_script_service(script_name)?;
-        service.start_script(&script_name, forced_start, self, noexec, writer)
+        service.start_script(&script_name, forced_start, self.clone(), noexec, writer)
     }
 
-    pub fn stop_script(&self, script_name: &String, writer: &mut WriterWithTCP) -> Result<(), Error> {
+    pub fn stop_script(&self, script_name: &str, writer: &mut WriterWithTCP) -> Result<(), Error> {
         let (service, script_name) = self.get_script_service(script_name)?;
         service.stop_script(&script_name, writer)
     }
 
+    pub fn read_script_logs(&self, script_name: &str, count: usize) -> Result<Value, Error> {
+        let (service, script_name) = self.get_script_service(script_name)?;
+        service.get_script(&script_name).ok_or(build_script_not_found_error())?.read_logs(count)
+    }
+
+    fn stop_script_and_wait(&self, script_name: &str, writer: &mut WriterWithTCP) -> Result<(), Error> {
+        let (service, script_name) = self.get_script_service(script_name)?;
+        service.stop_script(&script_name, writer)?;
+        if let Some(script) = service.get_script(&script_name) {
+            script.wait_finish();
+        }
+        Ok(())
+    }
+
     pub fn stop_service(&self, service_name: &String, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
         let service = self.find_service(service_name)?;
-        service.stop(noexec, writer)
+        for script_name in self.get_service_scripts(service_name).iter().rev() {
+            self.stop_script_and_wait(script_name, writer)?;
+        }
+        service.run_post_stop_script(noexec, writer)
+    }
+
+    fn get_scripts_with_dependencies(&self, services: &HashSet<String>) -> HashSet<String> {
+        let scripts = self.start_order.iter()
+            .filter(|name| services.contains(name.split('.').next().unwrap()))
+            .cloned()
+            .collect::<HashSet<_>>();
+        self.dependencies.with_all_dependencies(&scripts)
     }
 
-    fn start_all(&'static self, services: &HashSet<String>, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
-        for name in services {
-            self.services.get(name).unwrap().start(false, self, noexec, writer)?;
+    fn start_all(self: &Arc<Self>, services: &HashSet<String>, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
+        let scripts = self.get_scripts_with_dependencies(services);
+        for script_name in &self.start_order {
+            if scripts.contains(script_name) {
+                self.start_script(false, script_name, noexec, writer)?;
+            }
         }
         Ok(())
     }
 
     fn stop_all(&self, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
+        self.stop_scripts(&self.start_order.iter().cloned().collect(), noexec, writer)
+    }
+
+    /// Stops the scripts in the reverse topological order, post-stop-script runs
+    /// when all the service scripts are stopped.
+    fn stop_scripts(&self, scripts: &HashSet<String>, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
         let mut could_not_stop = Vec::new();
-        for (name, service) in &self.services {
-            if service.stop(noexec, writer).is_err() {
-                could_not_stop.push(name.clone());
+        let mut scripts_to_stop: HashMap<&str, usize> = HashMap::new();
+        for script_name in &self.start_order {
+            *scripts_to_stop.entry(script_name.split('.').next().unwrap()).or_default() += 1;
+        }
+        // services with scripts left running are not counted down to zero
+        for script_name in self.start_order.iter().filter(|name| !scripts.contains(*name)) {
+            scripts_to_stop.remove(script_name.split('.').next().unwrap());
+        }
+        for script_name in self.start_order.iter().rev().filter(|name| scripts.contains(*name)) {
+            let service_name = script_name.split('.').next().unwrap();
+            if self.stop_script_and_wait(script_name, writer).is_err() &&
+                !could_not_stop.contains(&service_name.to_string()) {
+                could_not_stop.push(service_name.to_string());
+            }
+            let all_stopped = match scripts_to_stop.get_mut(service_name) {
+                Some(left) => { *left -= 1; *left == 0 }
+                None => false
+            };
+            if all_stopped && self.services.get(service_name).unwrap().run_post_stop_script(noexec, writer).is_err() &&
+                !could_not_stop.contains(&service_name.to_string()) {
+                could_not_stop.push(service_name.to_string());
             }
         }
         if !could_not_stop.is_empty() {
@@ -105,7 +339,7 @@ impl Services {
         Ok(())
     }
 
-    pub fn get_script_service(&self, script_name: &String) -> Result<(&Service, String), Error> {
+    pub fn get_script_service(&self, script_name: &str) -> Result<(&Service, String), Error> {
         let parts: Vec<&str> = script_name.split('.').collect();
         if parts.len() != 2 {
             return Err(build_invalid_script_name_error());
@@ -121,38 +355,86 @@ impl Services {
         Err(build_invalid_data_error_string(format!("service does not exists: {}", name)))
     }
 
-    pub fn report_status(&self, service_name: Option<&String>) -> String {
+    pub fn report_status(&self, service_name: Option<&String>, format: StatusFormat) -> String {
         self.services.iter()
             .filter(|(name, _service)|service_name == None || service_name.unwrap() == *name)
-            .map(|(name, service)|name.clone() + ":\n" + service.get_status_string().as_str())
+            .map(|(name, service)|name.clone() + ":\n" + service.get_status_string(format).as_str())
             .collect::<Vec<_>>()
             .join("\n")
     }
 
+    pub fn check_services_exist(&self, service_names: &[&String]) -> Result<(), Error> {
+        for name in service_names {
+            self.find_service(name)?;
+        }
+        Ok(())
+    }
+
+    /// Returns services status tree, all the services are included when the names list is empty.
+    pub fn report_status_json(&self, service_names: &[&String], format: StatusFormat) -> Result<Value, Error> {
+        self.check_services_exist(service_names)?;
+        let services = self.services.iter()
+            .filter(|(name, _service)| service_names.is_empty() || service_names.contains(name))
+            .map(|(name, service)| (name.clone(), service.get_status_json(format)))
+            .collect::<Map<_, _>>();
+        Ok(json!({"services": services}))
+    }
+
     pub fn wait_finish(&self) {
         self.services.iter()
             .for_each(|(_name, service)|service.wait_finish())
     }
 }
 
+impl Changes {
+    /// Compares the names before and after reload, names present in both are checked with is_changed.
+    fn new<'a>(previous: impl Iterator<Item = &'a String>, current: impl Iterator<Item = &'a String>,
+               is_changed: impl Fn(&String) -> bool) -> Changes {
+        let previous = previous.collect::<BTreeSet<_>>();
+        let current = current.collect::<BTreeSet<_>>();
+        Changes {
+            added: current.difference(&previous).map(|name| name.to_string()).collect(),
+            removed: previous.difference(&current).map(|name| name.to_string()).collect(),
+            changed: current.intersection(&previous).filter(|name| is_changed(name)).map(|name| name.to_string()).collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    fn to_json(&self) -> Value {
+        json!({"added": self.added, "removed": self.removed, "changed": self.changed})
+    }
+}
+
+impl Display for Changes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "added {:?}, removed {:?}, changed {:?}", self.added, self.removed, self.changed)
+    }
+}
+
 impl ServiceManager {
-    pub fn new(service_sets: &Hash, services: &Hash, init_cmd: Option<String>,
+    pub fn new(config: &Yaml, config_file: Option<String>, init_cmd: Option<String>,
                shutdown_cmd: Option<String>, noexec: bool) -> Result<ServiceManager, Error> {
-        let services = Services::new(services)?;
+        let services = Services::new(config)?;
+        let environment = Environment::default().with_level(config, "config")?;
         let init_command = match init_cmd {
-            Some(cmd) => Some(CommandToRun::new(cmd, None, None, None,None)?),
+            Some(cmd) => Some(CommandToRun::new(cmd, None, None, None, environment.clone())?),
             None => None
         };
         let shutdown_command = match shutdown_cmd {
-            Some(cmd) => Some(CommandToRun::new(cmd, None, None, None,None)?),
+            Some(cmd) => Some(CommandToRun::new(cmd, None, None, None, environment)?),
             None => None
         };
         let manager = ServiceManager {
-            service_sets: build_service_sets(service_sets, &services)?,
-            services,
+            config_file,
+            services: RwLock::new(Arc::new(services)),
+            service_locks: Mutex::new(BTreeMap::new()),
             init_command,
             shutdown_command
         };
+        manager.add_service_locks();
         manager.init(noexec)?;
         Ok(manager)
     }
@@ -166,10 +448,62 @@ impl ServiceManager {
         Ok(())
     }
 
+    fn get_services(&self) -> Arc<Services> {
+        self.services.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Adds locks for the services which do not have them yet, locks are kept for the removed services.
+    fn add_service_locks(&self) {
+        let mut service_locks = self.service_locks.lock().unwrap();
+        for name in self.get_services().services.keys() {
+            service_locks.entry(name.clone()).or_insert_with(|| Box::leak(Box::new(Mutex::new(()))));
+        }
+    }
+
+    /// Locks the services in the name order so concurrent commands can not deadlock,
+    /// commands changing the same service are executed one after another.
+    fn lock_services<'a>(&self, service_names: impl Iterator<Item = &'a str>) -> ServiceLocks {
+        let locks = {
+            let service_locks = self.service_locks.lock().unwrap();
+            service_names.collect::<BTreeSet<_>>().into_iter()
+                .filter_map(|name| service_locks.get(name).copied())
+                .collect::<Vec<_>>()
+        };
+        locks.into_iter()
+            .map(|lock| lock.lock().unwrap_or_else(|e| e.into_inner()))
+            .collect()
+    }
+
+    /// Locks the services of the scripts returned by prepare together with its result and returns
+    /// the services they were computed from. Reload replaces the services only while it holds all the locks,
+    /// so when the services were replaced before the locks were taken, the locks are taken again.
+    fn lock_prepared<T>(&self, prepare: impl Fn(&Services) -> Result<(T, HashSet<String>), Error>)
+                        -> Result<(Arc<Services>, T, ServiceLocks), Error> {
+        loop {
+            let services = self.get_services();
+            let (result, scripts) = prepare(&services)?;
+            let locks = self.lock_services(scripts.iter().filter_map(|name| name.split('.').next()));
+            if Arc::ptr_eq(&services, &self.get_services()) {
+                return Ok((services, result, locks));
+            }
+        }
+    }
+
+    fn lock_all_services(&self) -> ServiceLocks {
+        let names = self.service_locks.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+        self.lock_services(names.iter().map(|name| name.as_str()))
+    }
+
+    fn lock_script_service(&self, script_name: &str) -> ServiceLocks {
+        self.lock_services(script_name.split('.').take(1))
+    }
+
     pub fn shutdown(&self, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
-        self.stop_all(noexec, writer)?;
+        let _locks = self.lock_all_services();
+        let services = self.get_services();
+        services.stop_all(noexec, writer)?;
         writer.write_string(format!("Waiting for all services to be finished..."));
-        self.services.wait_finish();
+        services.wait_finish();
         if let Some(cmd) = &self.shutdown_command {
             writer.write_string(format!("Starting shutdown script..."));
             cmd.run_sync(noexec)?;
@@ -178,46 +512,172 @@ impl ServiceManager {
         Ok(())
     }
 
-    pub fn up(&'static self, service_set_name: &String, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
-        let services = self.service_sets.get(service_set_name)
-            .ok_or(Error::new(ErrorKind::InvalidInput, "invalid service set name"))?;
-        self.services.start_all(services, noexec, writer)
+    pub fn up(&self, service_set_name: &String, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
+        let (services, service_set, _locks) = self.lock_prepared(|services| {
+            let service_set = services.service_sets.get(service_set_name)
+                .ok_or(Error::new(ErrorKind::NotFound, "service set not found"))?;
+            Ok((service_set.clone(), services.get_scripts_with_dependencies(service_set)))
+        })?;
+        services.start_all(&service_set, noexec, writer)
     }
 
-    pub fn stop_all(&self, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
-        self.services.stop_all(noexec, writer)
+    /// Starts the service or service.script together with the dependencies which are not ready yet.
+    fn start(&self, forced_start: bool, name: &String, noexec: bool,
+             writer: &mut WriterWithTCP) -> Result<(), Error> {
+        let (services, scripts, _locks) = self.lock_prepared(|services| {
+            let scripts = services.resolve_script_names(std::slice::from_ref(name))?;
+            let dependencies = services.dependencies.with_all_dependencies(&scripts);
+            Ok((scripts, dependencies))
+        })?;
+        services.start_with_dependencies(forced_start, &scripts, noexec, writer)
     }
 
-    pub fn start_service(&'static self, forced_start: bool, service_name: &String, noexec: bool,
+    pub fn start_service(&self, forced_start: bool, service_name: &String, noexec: bool,
                          writer: &mut WriterWithTCP) -> Result<(), Error> {
-        self.services.start_service(forced_start, service_name, noexec, writer)
+        self.start(forced_start, service_name, noexec, writer)
     }
 
     pub fn stop_service(&self, service_name: &String, noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
-        self.services.stop_service(service_name, noexec, writer)
+        let _locks = self.lock_services([service_name.as_str()].into_iter());
+        self.get_services().stop_service(service_name, noexec, writer)
+    }
+
+    /// Restarts the services and service.script scripts together with their active dependents:
+    /// the scripts are stopped in the reverse start order and started again in the start order
+    /// together with the dependencies which are not ready yet.
+    pub fn restart(&self, names: &[String], noexec: bool, writer: &mut WriterWithTCP) -> Result<(), Error> {
+        let (services, scripts, _locks) = self.lock_prepared(|services| {
+            let scripts = services.resolve_script_names(names)?;
+            let dependents = services.dependencies.with_all_dependents(&scripts);
+            let locked = services.dependencies.with_all_dependencies(&dependents);
+            Ok((scripts, locked))
+        })?;
+        let scripts = services.with_active_dependents(&scripts).into_iter().collect();
+        services.stop_scripts(&scripts, noexec, writer)?;
+        services.start_with_dependencies(false, &scripts, noexec, writer)
     }
 
-    pub fn start_script(&'static self, forced_start: bool, script_name: &String, noexec: bool,
+    /// Reads the configuration file again and applies the changes. Removed and changed scripts are stopped,
+    /// changed scripts which were active are started again together with their active dependents,
+    /// added scripts are started when their service has active scripts. Unchanged scripts keep running,
+    /// init-command and shutdown-command are not reloaded.
+    pub fn reload(&self, noexec: bool, writer: &mut WriterWithTCP) -> Result<Value, Error> {
+        let config_file = self.config_file.as_ref()
+            .ok_or(Error::new(ErrorKind::Unsupported, "configuration file is not known"))?;
+        let mut services = Services::new(&load_config(config_file)?)?;
+        let mut locks = self.lock_all_services();
+        let previous = self.get_services();
+        let diff = services.reuse_unchanged_scripts(&previous);
+        let scripts = &diff.scripts;
+
+        let changed = scripts.changed.iter().cloned().collect::<HashSet<_>>();
+        let to_restart = previous.with_active_dependents(&changed).into_iter()
+            .filter(|name| previous.get_script_state(name).is_active() && !scripts.removed.contains(name))
+            .collect::<HashSet<_>>();
+        let has_active_scripts = |service_name: &str| previous.get_service_scripts(service_name).iter()
+            .any(|name| previous.get_script_state(name).is_active());
+        let to_start = scripts.added.iter()
+            .filter(|name| has_active_scripts(name.split('.').next().unwrap()))
+            .cloned()
+            .chain(to_restart.iter().cloned())
+            .collect::<HashSet<_>>();
+        // post-stop-script runs only for the services which had active scripts
+        let to_stop = scripts.removed.iter().chain(scripts.changed.iter()).chain(to_restart.iter())
+            .filter(|name| previous.get_script_state(name).is_active())
+            .cloned()
+            .collect::<HashSet<_>>();
+        previous.stop_scripts(&to_stop, noexec, writer)?;
+
+        *self.services.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(services);
+        self.add_service_locks();
+        let services = self.get_services();
+        locks.extend(self.lock_services(services.services.keys()
+            .filter(|name| !previous.services.contains_key(*name))
+            .map(|name| name.as_str())));
+        let started = services.start_order.iter()
+            .filter(|name| to_start.contains(*name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for script_name in &started {
+            services.start_script(false, script_name, noexec, writer)?;
+        }
+        if scripts.is_empty() && diff.services.is_empty() && diff.service_sets.is_empty() {
+            writer.write_string("Configuration is not changed".to_string());
+        } else {
+            writer.write_string(format!("Scripts: {}", scripts));
+            writer.write_string(format!("Services: {}", diff.services));
+            writer.write_string(format!("Service sets: {}", diff.service_sets));
+            writer.write_string(format!("Restarted scripts: {:?}", started));
+        }
+        Ok(json!({"scripts": scripts.to_json(), "services": diff.services.to_json(),
+            "service_sets": diff.service_sets.to_json(), "restarted": started}))
+    }
+
+    pub fn start_script(&self, forced_start: bool, script_name: &String, noexec: bool,
                         writer: &mut WriterWithTCP) -> Result<(), Error> {
-        self.services.start_script(forced_start, script_name, noexec, writer)
+        if !script_name.contains('.') {
+            return Err(build_invalid_script_name_error());
+        }
+        self.start(forced_start, script_name, noexec, writer)
     }
 
     pub fn stop_script(&self, script_name: &String, writer: &mut WriterWithTCP) -> Result<(), Error> {
-        self.services.stop_script(script_name, writer)
+        let _locks = self.lock_script_service(script_name);
+        self.get_services().stop_script(script_name, writer)
+    }
+
+    pub fn report_status(&self, service_name: Option<&String>, format: StatusFormat) -> String {
+        self.get_services().report_status(service_name, format)
+    }
+
+    pub fn get_service_set_names(&self) -> Vec<String> {
+        self.get_services().service_sets.keys().cloned().collect()
+    }
+
+    pub fn get_service_names(&self) -> Vec<String> {
+        self.get_services().services.keys().cloned().collect()
+    }
+
+    pub fn get_script_names(&self) -> Vec<String> {
+        self.get_services().start_order.clone()
     }
 
-    pub fn report_status(&self, service_name: Option<&String>) -> String {
-        self.services.report_status(service_name)
+    pub fn report_service_sets_json(&self) -> Value {
+        let sets = self.get_services().service_sets.iter()
+            .map(|(name, services)| {
+                let mut services = services.iter().cloned().collect::<Vec<_>>();
+                services.sort();
+                (name.clone(), json!(services))
+            })
+            .collect::<Map<_, _>>();
+        json!({"sets": sets})
     }
 
-    pub fn wait_for_scripts(&self, scripts: &HashSet<String>) -> Result<(), Error> {
+    pub fn read_script_logs(&self, script_name: &str, count: usize) -> Result<Value, Error> {
+        self.get_services().read_script_logs(script_name, count)
+    }
+
+    pub fn check_services_exist(&self, service_names: &[&String]) -> Result<(), Error> {
+        self.get_services().check_services_exist(service_names)
+    }
+
+    pub fn report_status_json(&self, service_names: &[&String], format: StatusFormat) -> Result<Value, Error> {
+        self.get_services().report_status_json(service_names, format)
+    }
+
+    pub fn wait_for_scripts(&self, scripts: &HashSet<String>, timeout: Option<Duration>) -> Result<(), Error> {
+        let services = self.get_services();
         for script in scripts {
-            if !self.services.script_exists(script) {
-                return Err(Error::new(ErrorKind::InvalidInput, format!("Script does not exist: {}", script)));
+            if !services.script_exists(script) {
+                return Err(Error::new(ErrorKind::NotFound, format!("Script does not exist: {}", script)));
             }
         }
         let duration = Duration::from_secs(1);
-        while !self.services.check_scripts(scripts) {
+        let started = Instant::now();
+        while let Some(script) = scripts.iter().find(|s| !self.get_services().script_finished(s)) {
+            if timeout.is_some_and(|t| started.elapsed() >= t) {
+                return Err(Error::new(ErrorKind::TimedOut, format!("timed out waiting for script {}", script)));
+            }
             thread::sleep(duration);
         }
         Ok(())
@@ -254,4 +714,38 @@ fn build_service_sets(service_sets: &Hash, service_list: &Services) -> Result<Ha
         result.insert(name.as_str().unwrap().to_string(), services);
     }
     Ok(result)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+    use crate::service_manager::{Changes, Services};
+
+    fn build_services(config: &str) -> Services {
+        Services::new(&YamlLoader::load_from_str(config).unwrap()[0]).unwrap()
+    }
+
+    #[test]
+    fn test_changes() {
+        let previous = ["a".to_string(), "b".to_string(), "c".to_string()];
+        let current = ["b".to_string(), "c".to_string(), "d".to_string()];
+        let changes = Changes::new(previous.iter(), current.iter(), |name| name == "c");
+        assert_eq!(changes.added, vec!["d"]);
+        assert_eq!(changes.removed, vec!["a"]);
+        assert_eq!(changes.changed, vec!["c"]);
+        assert_eq!(changes.to_string(), "added [\"d\"], removed [\"a\"], changed [\"c\"]");
+    }
+
+    #[test]
+    fn test_reuse_unchanged_scripts() {
+        let config = "service-sets: {all: {services: [a, b]}}\n\
+            services: {a: {scripts: {x: {command: /bin/true}}}, b: {post-stop-script: /bin/true, scripts: {y: {command: /bin/true}}}}\n";
+        let previous = build_services(config);
+        let mut services = build_services(&config.replace("post-stop-script: /bin/true", "post-stop-script: /bin/false")
+            .replace("a: {", "a: {env: {A: 1}, "));
+        let diff = services.reuse_unchanged_scripts(&previous);
+        assert_eq!(diff.scripts.changed, vec!["a.x"]);
+        assert_eq!(diff.services.changed, vec!["a", "b"]);
+        assert!(build_services(config).reuse_unchanged_scripts(&previous).services.is_empty());
+    }
+}
@@ -0,0 +1,64 @@
+use std::io::Error;
+use std::str::FromStr;
+use yaml_rust::Yaml;
+use crate::utilities::build_invalid_data_error_string;
+
+fn signal_from_name(name: &str) -> Option<i32> {
+    let short_name = name.strip_prefix("SIG").unwrap_or(name);
+    match short_name {
+        "HUP" => Some(libc::SIGHUP),
+        "INT" => Some(libc::SIGINT),
+        "QUIT" => Some(libc::SIGQUIT),
+        "KILL" => Some(libc::SIGKILL),
+        "USR1" => Some(libc::SIGUSR1),
+        "USR2" => Some(libc::SIGUSR2),
+        "TERM" => Some(libc::SIGTERM),
+        _ => i32::from_str(short_name).ok()
+    }
+}
+
+/// Parses signal name (SIGTERM, TERM) or number, SIGTERM is used when the value is missing.
+pub fn parse_signal(item: &Yaml, name: &String) -> Result<i32, Error> {
+    let signal = match item {
+        Yaml::BadValue => Some(libc::SIGTERM),
+        Yaml::String(s) => signal_from_name(s.to_uppercase().as_str()),
+        Yaml::Integer(i) => i32::try_from(*i).ok(),
+        _ => None
+    };
+    match signal {
+        Some(s) if s > 0 && s < 32 => Ok(s),
+        _ => Err(build_invalid_data_error_string(format!("stop_signal is invalid in script {}", name)))
+    }
+}
+
+/// Sends the signal to every process in the group, scripts are started as group leaders
+/// so the group id is the script process id.
+pub fn send_group_signal(pgid: u32, signal: i32) -> Result<(), Error> {
+    if unsafe { libc::kill(-(pgid as libc::pid_t), signal) } == 0 {
+        Ok(())
+    } else {
+        Err(Error::last_os_error())
+    }
+}
+
+pub fn process_group_exists(pgid: u32) -> bool {
+    send_group_signal(pgid, 0).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::Yaml;
+    use crate::signals::parse_signal;
+
+    #[test]
+    fn test_parse_signal() {
+        let name = "test".to_string();
+        assert_eq!(parse_signal(&Yaml::BadValue, &name).unwrap(), libc::SIGTERM);
+        assert_eq!(parse_signal(&Yaml::String("SIGINT".to_string()), &name).unwrap(), libc::SIGINT);
+        assert_eq!(parse_signal(&Yaml::String("quit".to_string()), &name).unwrap(), libc::SIGQUIT);
+        assert_eq!(parse_signal(&Yaml::Integer(9), &name).unwrap(), libc::SIGKILL);
+        assert!(parse_signal(&Yaml::String("SIGNONE".to_string()), &name).is_err());
+        assert!(parse_signal(&Yaml::Integer(((1i64) << 32) + 15), &name).is_err());
+        assert!(parse_signal(&Yaml::Integer(0), &name).is_err());
+    }
+}
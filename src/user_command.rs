@@ -1,52 +1,109 @@
 use std::collections::HashSet;
-use std::io::{Error, ErrorKind, Read, Write};
-use std::net::{Shutdown, TcpStream};
+use std::io::{Error, ErrorKind};
 use std::process::exit;
+use std::str::FromStr;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+use serde_json::{json, Value};
+use crate::command_result::{build_result, OutputFormat};
+use crate::control_stream::ControlStream;
+use crate::events::{Event, subscribe};
+use crate::protocol::{event_frame, heartbeat_frame, output_frame, result_frame, write_frame};
+use crate::script::StatusFormat;
 use crate::service_manager::ServiceManager;
 
+/// Disconnected events clients are detected by the heartbeat write when no events arrive.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
 pub struct WriterWithTCP {
-    stream: Option<TcpStream>
+    stream: Option<ControlStream>,
+    format: OutputFormat,
+    request_id: u64,
+    outcomes: Vec<Value>,
+    messages: Vec<String>,
 }
 
 impl WriterWithTCP {
-    pub fn new(stream: Option<TcpStream>) -> WriterWithTCP {
-        WriterWithTCP{ stream }
+    pub fn new(stream: Option<ControlStream>, format: OutputFormat, request_id: u64) -> WriterWithTCP {
+        WriterWithTCP{ stream, format, request_id, outcomes: Vec::new(), messages: Vec::new() }
+    }
+
+    pub fn get_format(&self) -> OutputFormat {
+        self.format
     }
 
+    /// Messages are sent to the client as output frames and are collected into the command result.
     pub fn write_string(&mut self, string: String) {
         println!("{}",  string);
         if let Some(w) = &mut self.stream {
-            if writeln!(w, "{}", string).is_ok() {
-                let _ = w.flush();
-            }
+            let _ = write_frame(w, &output_frame(self.request_id, &string));
         }
+        self.messages.push(string);
     }
 
-    pub fn shutdown(&self) {
-        if let Some(s) = &self.stream {
-            if let Err(e) = s.shutdown(Shutdown::Both) {
-                println!("Stream shutdown error {}", e);
-            }
+    pub fn has_client(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub fn write_event(&mut self, event: &Event) -> Result<(), Error> {
+        match &mut self.stream {
+            Some(w) => write_frame(w, &event_frame(self.request_id, event)),
+            None => Ok(())
         }
     }
 
-    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, Error> {
-        self.stream.as_ref().unwrap().read(buffer)
+    pub fn write_heartbeat(&mut self) -> Result<(), Error> {
+        match &mut self.stream {
+            Some(w) => write_frame(w, &heartbeat_frame(self.request_id)),
+            None => Ok(())
+        }
+    }
+
+    pub fn add_outcome(&mut self, script_name: &str, outcome: &str, state: &str) {
+        self.outcomes.push(json!({"script": script_name, "outcome": outcome, "state": state}));
+    }
+
+    pub fn take_result(&mut self, command: &[String], result: &Result<Option<Value>, Error>) -> Value {
+        let outcomes = std::mem::take(&mut self.outcomes);
+        let messages = std::mem::take(&mut self.messages);
+        build_result(command, outcomes, messages, result)
+    }
+
+    /// Sends the final result frame to the client, without the client the command error is printed
+    /// in text format or the whole command result in json format.
+    pub fn write_result(&mut self, command: &[String], result: &Result<Option<Value>, Error>) {
+        let result_object = self.take_result(command, result);
+        if let Some(w) = &mut self.stream {
+            if let Err(e) = result {
+                println!("{}", e);
+            }
+            let _ = write_frame(w, &result_frame(self.request_id, result_object));
+            return;
+        }
+        match self.format {
+            OutputFormat::Text => if let Err(e) = result {
+                println!("{}", e);
+            },
+            OutputFormat::Json => println!("{}", result_object),
+        }
     }
 }
 
-pub fn run_user_command(parts: Vec<String>, manager: &'static ServiceManager, noexec: bool, writer: &mut WriterWithTCP)
-    -> Result<(), Error> {
+pub const USER_COMMANDS: [&str; 12] = ["up", "down", "start", "force-start", "stop", "restart", "reload", "status",
+    "wait_for_scripts", "events", "watch", "exit"];
+
+pub fn run_user_command(parts: &[String], manager: &'static ServiceManager, noexec: bool, writer: &mut WriterWithTCP)
+    -> Result<Option<Value>, Error> {
     writer.write_string(format!("Running command {:?}", parts));
     if parts.is_empty() {
         return Err(Error::new(ErrorKind::InvalidInput, "empty command"));
     }
     return match parts[0].as_str() {
         "up" => if parts.len() == 2 {
-            manager.up(&parts[1], noexec, writer)
+            manager.up(&parts[1], noexec, writer).map(|_| None)
         } else { Err(build_invalid_command_error()) },
         "down" => if parts.len() == 1 {
-            manager.shutdown(noexec, writer)
+            manager.shutdown(noexec, writer).map(|_| None)
         } else { Err(build_invalid_command_error()) },
         "start" => if parts.len() >= 2 {
             for i in 1..parts.len() {
@@ -56,7 +113,7 @@ pub fn run_user_command(parts: Vec<String>, manager: &'static ServiceManager, no
                     manager.start_service(false, &parts[i], noexec, writer)?;
                 }
             }
-            Ok(())
+            Ok(None)
         } else { Err(build_invalid_command_error()) },
         "force-start" => if parts.len() >= 2 {
             for i in 1..parts.len() {
@@ -66,7 +123,7 @@ pub fn run_user_command(parts: Vec<String>, manager: &'static ServiceManager, no
                     manager.start_service(true, &parts[i], noexec, writer)?;
                 }
             }
-            Ok(())
+            Ok(None)
         } else { Err(build_invalid_command_error()) },
         "stop" => if parts.len() >= 2 {
             for i in 1..parts.len() {
@@ -76,38 +133,112 @@ pub fn run_user_command(parts: Vec<String>, manager: &'static ServiceManager, no
                     manager.stop_service(&parts[i], noexec, writer)?;
                 }
             }
-            Ok(())
+            Ok(None)
         } else { Err(build_invalid_command_error()) },
-        "status" => if parts.len() == 1 {
-            writer.write_string(manager.report_status(None));
-            Ok(())
-        } else {
-            for i in 1..parts.len() {
-                writer.write_string(manager.report_status(Some(&parts[i])));
+        "restart" => if parts.len() >= 2 {
+            manager.restart(&parts[1..], noexec, writer).map(|_| None)
+        } else { Err(build_invalid_command_error()) },
+        "reload" => if parts.len() == 1 {
+            manager.reload(noexec, writer).map(Some)
+        } else { Err(build_invalid_command_error()) },
+        "status" => {
+            let (format, skip) = match parts.get(1).map(|p| p.as_str()) {
+                Some("--long") => (StatusFormat::Long, 2),
+                Some("--history") => (StatusFormat::History, 2),
+                _ => (StatusFormat::Short, 1)
+            };
+            let service_names: Vec<&String> = parts.iter().skip(skip).collect();
+            if writer.get_format() == OutputFormat::Json {
+                return manager.report_status_json(&service_names, format).map(Some);
+            }
+            manager.check_services_exist(&service_names)?;
+            if service_names.is_empty() {
+                writer.write_string(manager.report_status(None, format));
+            } else {
+                for service_name in service_names {
+                    writer.write_string(manager.report_status(Some(service_name), format));
+                }
             }
-            Ok(())
+            Ok(None)
         },
-        "wait_for_scripts" => if parts.len() >= 2 {
-            let scripts: HashSet<String> = parts.iter()
-                .skip(1)
-                .map(|s|s.clone())
-                .collect::<HashSet<_>>();
-            manager.wait_for_scripts(&scripts)
-        } else { Err(build_invalid_command_error()) },
+        "wait_for_scripts" => {
+            let (timeout, scripts) = parse_wait_for_scripts(&parts[1..])?;
+            manager.wait_for_scripts(&scripts, timeout).map(|_| None)
+        },
+        "events" | "watch" => watch_events(&parts[1..], writer).map(|_| None),
         "exit" => {
-            let _ = manager.shutdown(noexec, writer);
+            let result = manager.shutdown(noexec, writer).map(|_| None);
+            writer.write_result(parts, &result);
             exit(0);
         },
         _ => Err(Error::new(ErrorKind::InvalidInput, "unknown command"))
     };
 }
 
+/// Streams the events of the given services or scripts (all when no names are given)
+/// until the client disconnects.
+fn watch_events(names: &[String], writer: &mut WriterWithTCP) -> Result<(), Error> {
+    if !writer.has_client() {
+        return Err(Error::new(ErrorKind::InvalidInput, "events command requires a client connection"));
+    }
+    let subscription = subscribe();
+    loop {
+        match subscription.recv_timeout(HEARTBEAT_INTERVAL) {
+            Ok(event) => if event.matches(names) {
+                writer.write_event(&event)?;
+            },
+            Err(RecvTimeoutError::Timeout) => writer.write_heartbeat()?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(())
+        }
+    }
+}
+
+/// Parses [timeout N] script... arguments of wait_for_scripts command.
+fn parse_wait_for_scripts(parts: &[String]) -> Result<(Option<Duration>, HashSet<String>), Error> {
+    let (timeout, skip) = if parts.first().is_some_and(|p| p == "timeout") {
+        let seconds = parts.get(1).ok_or(build_invalid_command_error())?;
+        let seconds = u64::from_str(seconds)
+            .map_err(|_e| Error::new(ErrorKind::InvalidInput, "invalid timeout value"))?;
+        (Some(Duration::from_secs(seconds)), 2)
+    } else {
+        (None, 0)
+    };
+    if parts.len() <= skip {
+        return Err(build_invalid_command_error());
+    }
+    Ok((timeout, parts.iter().skip(skip).cloned().collect()))
+}
+
 fn build_invalid_command_error() -> Error {
     Error::new(ErrorKind::InvalidInput, "invalid command")
 }
 
 pub fn run_user_commands(commands: Vec<String>, manager: &'static ServiceManager, noexec: bool, mut writer: WriterWithTCP) {
-    if let Err(e) = run_user_command(commands, manager, noexec, &mut writer) {
-        let _ = writer.write_string(format!("{}", e));
+    let result = run_user_command(&commands, manager, noexec, &mut writer);
+    writer.write_result(&commands, &result);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::io::ErrorKind;
+    use std::time::Duration;
+    use crate::user_command::parse_wait_for_scripts;
+
+    fn parse(line: &str) -> Result<(Option<Duration>, HashSet<String>), std::io::Error> {
+        parse_wait_for_scripts(&line.split_whitespace().map(|p| p.to_string()).collect::<Vec<_>>())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_wait_for_scripts() {
+        let (timeout, scripts) = parse("timeout 5 a.b c.d").unwrap();
+        assert_eq!(timeout, Some(Duration::from_secs(5)));
+        assert_eq!(scripts, HashSet::from(["a.b".to_string(), "c.d".to_string()]));
+        assert_eq!(parse("a.b").unwrap(), (None, HashSet::from(["a.b".to_string()])));
+        assert_eq!(parse("timeout abc a.b").unwrap_err().to_string(), "invalid timeout value");
+        assert_eq!(parse("timeout -1 a.b").unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(parse("timeout").is_err());
+        assert!(parse("timeout 5").is_err());
+        assert!(parse("").is_err());
+    }
+}
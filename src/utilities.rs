@@ -1,4 +1,6 @@
-use std::io::{Error, ErrorKind};
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub fn build_invalid_data_error_str(text: &str) -> Error {
     Error::new(ErrorKind::InvalidData, text)
@@ -7,3 +9,56 @@ pub fn build_invalid_data_error_str(text: &str) -> Error {
 pub fn build_invalid_data_error_string(text: String) -> Error {
     Error::new(ErrorKind::InvalidData, text)
 }
+
+const MAX_TAIL_BYTES: u64 = 1024 * 1024;
+
+/// Returns up to count last lines of the file, only the last megabyte of the file is read.
+pub fn read_last_lines(file_name: &str, count: usize) -> Result<Vec<String>, Error> {
+    let mut file = File::open(file_name)?;
+    let length = file.metadata()?.len();
+    file.seek(SeekFrom::Start(length.saturating_sub(MAX_TAIL_BYTES)))?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
+    let contents = String::from_utf8_lossy(&contents);
+    let lines = contents.lines().collect::<Vec<_>>();
+    Ok(lines[lines.len().saturating_sub(count)..].iter().map(|l| l.to_string()).collect())
+}
+
+/// Formats the time as local "YYYY-MM-DD HH:MM:SS".
+pub fn format_time(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&seconds, &mut tm) }.is_null() {
+        return seconds.to_string();
+    }
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday,
+            tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+/// Formats the duration as "1d 2h 3m 4s" skipping the leading zero parts.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let parts = [(seconds / 86400, "d"), (seconds / 3600 % 24, "h"), (seconds / 60 % 60, "m"), (seconds % 60, "s")];
+    let result = parts.iter()
+        .skip_while(|(value, unit)| *value == 0 && *unit != "s")
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect::<Vec<_>>();
+    result.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::utilities::read_last_lines;
+
+    #[test]
+    fn test_read_last_lines() {
+        let file_name = std::env::temp_dir().join(format!("runner_test_{}.log", std::process::id()));
+        fs::write(&file_name, "1\n2\n3\n").unwrap();
+        let file_name = file_name.to_str().unwrap();
+        assert_eq!(read_last_lines(file_name, 2).unwrap(), vec!["2", "3"]);
+        assert_eq!(read_last_lines(file_name, 5).unwrap(), vec!["1", "2", "3"]);
+        fs::remove_file(file_name).unwrap();
+        assert!(read_last_lines(file_name, 1).is_err());
+    }
+}
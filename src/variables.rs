@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::env;
+use std::io::Error;
+use std::path::Path;
+use yaml_rust::Yaml;
+use crate::utilities::build_invalid_data_error_string;
+
+/// Variables visible in the configuration values: built-ins (SERVICE, SCRIPT, CONFIG_DIR) and
+/// vars map entries, the environment is used for the names not found here.
+#[derive(Clone)]
+struct Variables {
+    values: HashMap<String, String>,
+}
+
+impl Variables {
+    fn get(&self, name: &str) -> Option<String> {
+        self.values.get(name).cloned().or_else(|| env::var(name).ok())
+    }
+
+    fn with(&self, name: &str, value: &str) -> Variables {
+        let mut result = self.clone();
+        result.values.insert(name.to_string(), value.to_string());
+        result
+    }
+}
+
+/// Replaces ${VAR} and ${VAR:-default} in the text, the default is used when the variable
+/// is not set or empty. $${ is replaced with ${, any other $ is kept as is.
+fn substitute(text: &str, get: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(position) = rest.find('$') {
+        result.push_str(&rest[..position]);
+        rest = &rest[position..];
+        if rest.starts_with("$${") {
+            result.push_str("${");
+            rest = &rest[3..];
+        } else if rest.starts_with("${") {
+            let end = rest.find('}').ok_or(format!("unterminated variable in {}", text))?;
+            let expression = &rest[2..end];
+            let (name, default) = match expression.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (expression, None)
+            };
+            if name.is_empty() {
+                return Err(format!("empty variable name in {}", text));
+            }
+            let value = match (get(name).filter(|v| !v.is_empty() || default.is_none()), default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => default.to_string(),
+                (None, None) => return Err(format!("unknown variable {}", name))
+            };
+            result.push_str(&value);
+            rest = &rest[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn interpolate(item: &mut Yaml, variables: &Variables, path: &str) -> Result<(), Error> {
+    match item {
+        Yaml::String(text) => {
+            *text = substitute(text, |name| variables.get(name))
+                .map_err(|e| build_invalid_data_error_string(format!("{} in {}", e, path)))?;
+        }
+        Yaml::Array(items) => {
+            for (i, value) in items.iter_mut().enumerate() {
+                interpolate(value, variables, &format!("{}[{}]", path, i))?;
+            }
+        }
+        Yaml::Hash(items) => {
+            for (key, value) in items.iter_mut() {
+                interpolate(value, variables, &format!("{}.{}", path, key.as_str().unwrap_or("?")))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn build_variables(config: &Yaml, config_file: &str) -> Result<Variables, Error> {
+    let config_dir = Path::new(config_file).canonicalize()?
+        .parent()
+        .map(|d| d.display().to_string())
+        .unwrap_or_default();
+    let mut variables = Variables { values: HashMap::new() }.with("CONFIG_DIR", &config_dir);
+    if let Some(vars) = config["vars"].as_hash() {
+        // a variable can use the variables defined before it
+        for (name, value) in vars {
+            let name = name.as_str()
+                .ok_or(build_invalid_data_error_string("vars name is invalid".to_string()))?;
+            let mut value = match value {
+                Yaml::String(s) => Yaml::String(s.clone()),
+                Yaml::Integer(i) => Yaml::String(i.to_string()),
+                Yaml::Real(r) => Yaml::String(r.clone()),
+                Yaml::Boolean(b) => Yaml::String(b.to_string()),
+                _ => return Err(build_invalid_data_error_string(format!("vars value is invalid for {}", name)))
+            };
+            interpolate(&mut value, &variables, &format!("vars.{}", name))?;
+            variables = variables.with(name, value.as_str().unwrap());
+        }
+    }
+    Ok(variables)
+}
+
+/// Substitutes the variables in all the configuration values, SERVICE is set inside a service
+/// and SCRIPT inside a script. Unknown variable is a load error.
+pub fn interpolate_config(config: &mut Yaml, config_file: &str) -> Result<(), Error> {
+    let variables = build_variables(config, config_file)?;
+    if let Yaml::Hash(items) = config {
+        for (key, value) in items.iter_mut() {
+            match (key.as_str(), value) {
+                (Some("vars"), _) => {}
+                (Some("services"), Yaml::Hash(services)) => {
+                    for (service_name, service) in services.iter_mut() {
+                        let service_name = service_name.as_str().unwrap_or("?");
+                        interpolate_service(service, &variables.with("SERVICE", service_name),
+                                            &format!("services.{}", service_name))?;
+                    }
+                }
+                (key, value) => interpolate(value, &variables, key.unwrap_or("?"))?
+            }
+        }
+    }
+    Ok(())
+}
+
+fn interpolate_service(service: &mut Yaml, variables: &Variables, path: &str) -> Result<(), Error> {
+    if let Yaml::Hash(items) = service {
+        for (key, value) in items.iter_mut() {
+            match (key.as_str(), value) {
+                (Some("scripts"), Yaml::Hash(scripts)) => {
+                    for (script_name, script) in scripts.iter_mut() {
+                        let script_name = script_name.as_str().unwrap_or("?");
+                        interpolate(script, &variables.with("SCRIPT", script_name),
+                                    &format!("{}.scripts.{}", path, script_name))?;
+                    }
+                }
+                (key, value) => interpolate(value, variables, &format!("{}.{}", path, key.unwrap_or("?")))?
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust::YamlLoader;
+    use crate::variables::{interpolate_config, substitute};
+
+    #[test]
+    fn test_substitute() {
+        let get = |name: &str| match name {
+            "A" => Some("a".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None
+        };
+        assert_eq!(substitute("x${A}y $WD ~ $${A}", get).unwrap(), "xay $WD ~ ${A}");
+        assert_eq!(substitute("${B:-/tmp}/${EMPTY:-e}${EMPTY}", get).unwrap(), "/tmp/e");
+        assert_eq!(substitute("${B}", get).unwrap_err(), "unknown variable B");
+        assert!(substitute("${A", get).is_err());
+
+        let mut config = YamlLoader::load_from_str("vars:\n  root: /opt\n  kafka: ${root}/kafka\n\
+            services:\n  kafka:\n    scripts:\n      server:\n        command: ${kafka}/bin/${SERVICE}-${SCRIPT}.sh\n\
+            \x20       log_file: ${CONFIG_DIR}/${SCRIPT}.log\n").unwrap().swap_remove(0);
+        interpolate_config(&mut config, "Cargo.toml").unwrap();
+        let script = &config["services"]["kafka"]["scripts"]["server"];
+        assert_eq!(script["command"].as_str(), Some("/opt/kafka/bin/kafka-server.sh"));
+        assert_eq!(script["log_file"].as_str().unwrap(),
+                   format!("{}/server.log", std::env::current_dir().unwrap().display()));
+
+        let mut config = YamlLoader::load_from_str("init-command: ${SCRIPT}").unwrap().swap_remove(0);
+        assert_eq!(interpolate_config(&mut config, "Cargo.toml").unwrap_err().to_string(),
+                   "unknown variable SCRIPT in init-command");
+    }
+}